@@ -0,0 +1,88 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Config file to use instead of searching the standard locations
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Show a live status view instead of log output (falls back to logs when stdout isn't a TTY)
+    #[arg(long)]
+    pub tui: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Write a starter config with one path, to --config or the first standard location. Asks for
+    /// whatever isn't given as a flag when run in a terminal
+    Init {
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        source: Option<PathBuf>,
+        #[arg(long)]
+        destination: Option<PathBuf>,
+        /// Copy, Sync or Sort
+        #[arg(long)]
+        watcher_type: Option<String>,
+        /// Put files into a folder per title (true or false)
+        #[arg(long)]
+        place_in_sub: Option<bool>,
+        /// Replace an existing config
+        #[arg(long)]
+        force: bool,
+    },
+    /// Load and validate the config, print it with defaults and paths resolved, then exit
+    VerifyConfig {
+        /// Print JSON instead of TOML
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a table of the configured paths and their resolved options, then exit
+    ListWatchers,
+    /// Compare the source and destination of every Sync path and list what differs. Exits
+    /// non-zero when anything does
+    Check,
+    /// Organize one file or folder right away with the rules of the path called NAME, and print
+    /// where it ended up
+    Reprocess {
+        path: PathBuf,
+        /// Path (by name) whose rules to use, by default the one whose source holds PATH
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Merge title folders whose names only differ in case or whitespace, in the destinations of
+    /// every Copy and Sort path (or only NAME). Moves files, so try --dry-run first
+    MergeDuplicates {
+        #[arg(long)]
+        name: Option<String>,
+        /// Only print what would be merged
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print only the absolute path FILE would be organized to by the path called NAME, without
+    /// touching anything. FILE doesn't have to exist, a bare file name is taken as in the source
+    PrintDestination {
+        file: PathBuf,
+        #[arg(long)]
+        name: String,
+    },
+    /// Try creating, watching, copying, syncing and deleting files in a throwaway folder inside
+    /// DIR (the system's temp folder by default) and report which work. Needs no config, exits
+    /// non-zero when any step fails
+    SelfTest {
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Watch the sources of every path (or only NAME) and log each raw filesystem event, without
+    /// organizing anything. Shows whether a mount reports events at all, until Ctrl-C
+    WatchEvents {
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
@@ -0,0 +1,289 @@
+use crate::cli::Command;
+use crate::config::{self, Config, PathConfig, WatcherTypeConfig};
+use crate::sync_watcher::SyncWatcher;
+use crate::watch::Watched;
+use crate::{Organizer, init, merge, self_test, watch};
+use anyhow::bail;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+// One-shot subcommands that inspect the config instead of starting the watchers
+pub async fn run(command: Command, config: Option<PathBuf>) -> Result<(), anyhow::Error> {
+    // Meant for checking a deployment before it has a config
+    if let Command::SelfTest { dir } = command {
+        return self_test::run(dir).await;
+    }
+    // The config doesn't exist yet
+    if let Command::Init {
+        name,
+        source,
+        destination,
+        watcher_type,
+        place_in_sub,
+        force,
+    } = command
+    {
+        let answers = init::Answers {
+            name,
+            source,
+            destination,
+            watcher_type,
+            place_in_sub,
+        };
+        return init::run(config, answers, force).await;
+    }
+
+    let config_path = config::find_config_path(config)?;
+    match command {
+        Command::VerifyConfig { json } => verify_config(&config_path, json).await,
+        Command::ListWatchers => list_watchers(&config_path).await,
+        Command::Check => check(&config_path).await,
+        Command::Reprocess { path, name } => reprocess(&config_path, &path, name).await,
+        Command::MergeDuplicates { name, dry_run } => {
+            merge_duplicates(&config_path, name, dry_run).await
+        }
+        Command::PrintDestination { file, name } => {
+            print_destination(&config_path, &file, &name).await
+        }
+        Command::WatchEvents { name } => watch_events(&config_path, name).await,
+        Command::SelfTest { .. } | Command::Init { .. } => unreachable!(),
+    }
+}
+
+async fn verify_config(config_path: &Path, json: bool) -> Result<(), anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+    } else {
+        println!("# Loaded from {}", config_path.display());
+        println!("{}", toml::to_string_pretty(&config)?);
+    }
+
+    Ok(())
+}
+
+async fn list_watchers(config_path: &Path) -> Result<(), anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    let header = ["NAME", "SOURCE", "DESTINATION", "TYPE", "OPTIONS"].map(String::from);
+    let rows: Vec<[String; 5]> = config
+        .paths
+        .iter()
+        .map(|path| {
+            // Sources are often created later by the download client, so this is only a note
+            let missing = if path.source.exists() {
+                ""
+            } else {
+                " (missing)"
+            };
+            [
+                path.name.clone(),
+                format!("{}{missing}", path.source.display()),
+                path.destination.display().to_string(),
+                format!("{:?}", path.watcher_type),
+                watcher_options(path),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+async fn check(config_path: &Path) -> Result<(), anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    let mut drifted = 0;
+    for path in config
+        .paths
+        .into_iter()
+        .filter(|path| matches!(path.watcher_type, WatcherTypeConfig::Sync))
+    {
+        let name = path.name.clone();
+        let watcher = SyncWatcher::new(path);
+        let drift = tokio::task::spawn_blocking(move || watcher.drift()).await??;
+        if drift.is_empty() {
+            println!("{name}: in sync");
+            continue;
+        }
+
+        drifted += 1;
+        println!(
+            "{name}: {} missing, {} extra, {} with different sizes",
+            drift.missing.len(),
+            drift.extra.len(),
+            drift.mismatched.len()
+        );
+        let lists = [
+            ("missing", &drift.missing),
+            ("extra", &drift.extra),
+            ("size differs", &drift.mismatched),
+        ];
+        for (label, paths) in lists {
+            for path in paths {
+                println!("  {label}: {}", path.display());
+            }
+        }
+    }
+
+    if drifted > 0 {
+        bail!("{drifted} Sync paths have drifted");
+    }
+
+    Ok(())
+}
+
+async fn reprocess(
+    config_path: &Path,
+    path: &Path,
+    name: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let organizer = Organizer::from_config(config_path).await?;
+    let path = std::path::absolute(path)?;
+    let destination = match name {
+        Some(name) => organizer.organize_file_as(&name, &path).await?,
+        None => organizer.organize_file(&path).await?,
+    };
+    println!("{} -> {}", path.display(), destination.display());
+    Ok(())
+}
+
+async fn print_destination(
+    config_path: &Path,
+    file: &Path,
+    name: &str,
+) -> Result<(), anyhow::Error> {
+    let organizer = Organizer::from_config(config_path).await?;
+    let destination = organizer.destination_for(name, file)?;
+    println!("{}", destination.display());
+    Ok(())
+}
+
+async fn merge_duplicates(
+    config_path: &Path,
+    name: Option<String>,
+    dry_run: bool,
+) -> Result<(), anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    let paths: Vec<PathConfig> = config
+        .paths
+        .into_iter()
+        .filter(|path| name.as_ref().is_none_or(|name| &path.name == name))
+        .filter(|path| !matches!(path.watcher_type, WatcherTypeConfig::Sync))
+        .collect();
+    if paths.is_empty() {
+        bail!("No Copy or Sort path to merge folders in");
+    }
+
+    for path in paths {
+        let (name, dry_run) = (path.name.clone(), dry_run || path.dry_run);
+        let merged =
+            tokio::task::spawn_blocking(move || merge::merge_duplicates(&path, dry_run)).await?;
+        let verb = if dry_run { "would merge" } else { "merged" };
+        println!("{name}: {verb} {merged} folders");
+    }
+
+    Ok(())
+}
+
+async fn watch_events(config_path: &Path, name: Option<String>) -> Result<(), anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    let paths: Vec<PathConfig> = config
+        .paths
+        .into_iter()
+        .filter(|path| name.as_ref().is_none_or(|name| &path.name == name))
+        .collect();
+    if paths.is_empty() {
+        bail!("No path to watch");
+    }
+
+    info!(
+        "Watching with the {:?} backend, press Ctrl-C to stop",
+        RecommendedWatcher::kind()
+    );
+    let mut join_set = JoinSet::new();
+    for path in paths {
+        // Sort paths only ever watch the top of their source
+        let mode = match path.watcher_type {
+            WatcherTypeConfig::Sort => RecursiveMode::NonRecursive,
+            _ => RecursiveMode::Recursive,
+        };
+        let (mut watcher, mut rx) =
+            watch::event_channel(path.channel_capacity(), path.poll_fallback(), |_| true)?;
+        watcher.watch(&path.source, mode)?;
+        info!("{}: watching {}", path.name, path.source.display());
+
+        join_set.spawn(async move {
+            while let Some(watched) =
+                watch::next_event(&mut rx, &mut watcher, &path.source, mode).await
+            {
+                let Watched::Event(event) = watched else {
+                    warn!(name = %path.name, "Events were dropped while the channel was full");
+                    continue;
+                };
+                info!(
+                    name = %path.name,
+                    kind = ?event.kind,
+                    paths = ?event.paths,
+                    attrs = ?event.attrs,
+                    "Event"
+                );
+            }
+        });
+    }
+
+    tokio::select! {
+        _ = join_set.join_all() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
+
+fn watcher_options(path: &PathConfig) -> String {
+    let mut options = vec![
+        format!("wait {}s", path.wait_time().as_secs()),
+        format!("on_conflict {:?}", path.on_conflict()),
+        format!("dedup {:?}", path.dedup),
+    ];
+
+    if path.place_in_sub {
+        let template = path.folder_template.as_deref().unwrap_or("{title}");
+        options.push(format!("subfolder {template}"));
+    }
+    if let Some(template) = &path.rename_template {
+        options.push(format!("rename {template}"));
+    }
+    if let Some(max_concurrent) = path.max_concurrent {
+        options.push(format!("max_concurrent {max_concurrent}"));
+    }
+    if path.delete_source {
+        match path.delete_after_secs {
+            Some(delay) => options.push(format!("delete_source after {delay}s")),
+            None => options.push("delete_source".to_string()),
+        }
+    }
+    if let Some(keep) = path.keep_latest {
+        options.push(format!("keep_latest {keep}"));
+    }
+    if path.dry_run {
+        options.push("dry_run".to_string());
+    }
+
+    options.join(", ")
+}
@@ -0,0 +1,1617 @@
+use crate::copy::CopyOptions;
+use crate::error::OrganizerError;
+use crate::remote;
+use anyhow::{anyhow, bail};
+use notify::RecursiveMode;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+const CONFIG_DIR_NAME: &str = "anime-organizer";
+const CONFIG_FILE_NAME: &str = "paths.toml";
+const ENV_PREFIX: &str = "AORG_";
+const DEFAULT_WAIT_TIME_SECS: u64 = 5;
+const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+const DEFAULT_SYNC_WORKERS: usize = 4;
+const DEFAULT_RESOLVER_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const MIN_COPY_BUFFER_BYTES: usize = 4 * 1024;
+// Metadata dropped by file browsers and NAS indexers, matched case-insensitively
+const JUNK_NAMES: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini", "@eaDir"];
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Config {
+    pub(crate) paths: Vec<PathConfig>,
+    // Fallbacks for per-path settings that a path leaves out
+    #[serde(default)]
+    pub(crate) defaults: Defaults,
+    // Where scheduled work (e.g. delayed source deletions) is persisted across restarts
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) state_file: Option<PathBuf>,
+    // Unix socket accepting line commands such as `status` and `resume <name>`
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) control_socket: Option<PathBuf>,
+    // File the periodic report of names no title could be parsed from is appended to
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) unparsed_log: Option<PathBuf>,
+    // Log a summary line per path this often, off when unset
+    pub(crate) heartbeat_secs: Option<u64>,
+    #[serde(default)]
+    pub(crate) runtime: RuntimeConfig,
+    pub(crate) log: Option<LogConfig>,
+    // On Ctrl-C or SIGTERM, how long to wait for detected files to finish moving before exiting
+    // anyway (default 30). A copy stuck on a dead mount is abandoned rather than waited out
+    pub(crate) shutdown_timeout_secs: Option<u64>,
+}
+
+// Thread pools of the tokio runtime, read before it starts. Copies run on the blocking pool, so
+// max_blocking_threads caps how many can be in flight across all paths (tokio's default is 512).
+// On a 4-core NAS, worker_threads = 2 and max_blocking_threads = 16 keep the disks busy without
+// thrashing them; the defaults (one worker per core) suit bigger machines
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct RuntimeConfig {
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+}
+
+// A log file, rotated by tracing-appender. Old files beyond max_files are deleted, which is what
+// keeps a daemon's log from growing without bound
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LogConfig {
+    #[serde(deserialize_with = "expand_path")]
+    pub directory: PathBuf,
+    #[serde(default = "default_log_file_name")]
+    pub file_name: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    pub max_files: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    Hourly,
+    // One file that is never rotated
+    Never,
+}
+
+// What has to be known before the runtime and logging start, read synchronously from the
+// `[runtime]` and `[log]` tables. Anything wrong with the file gives the defaults, Config::load
+// reports it properly afterwards
+#[derive(Default, Deserialize)]
+pub struct StartupConfig {
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    pub log: Option<LogConfig>,
+}
+
+impl StartupConfig {
+    // Read before logging is set up, so problems go to stderr. The defaults are used instead, the
+    // full load later reports the config's problems again. A missing file (e.g. one `init` is
+    // about to write) isn't one
+    pub fn load(path: &Path) -> Self {
+        let loaded = match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<StartupConfig>(&contents)
+                .map_err(|e| format!("could not parse {}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StartupConfig::default()),
+            Err(e) => Err(format!("could not read {}: {e}", path.display())),
+        };
+        let mut config = loaded.unwrap_or_else(|e| {
+            eprintln!("Using the default runtime and log settings, {e}");
+            StartupConfig::default()
+        });
+        if let Some(log) = &mut config.log
+            && let Ok(directory) =
+                std::path::absolute(path.parent().unwrap_or(Path::new(".")).join(&log.directory))
+        {
+            log.directory = directory;
+        }
+        config
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct PathConfig {
+    #[serde(deserialize_with = "expand_path")]
+    pub(crate) source: PathBuf,
+    #[serde(deserialize_with = "expand_path")]
+    pub(crate) destination: PathBuf,
+    pub(crate) place_in_sub: bool,
+    pub(crate) name: String,
+    pub(crate) watcher_type: WatcherTypeConfig,
+    // Subfolder layout used with place_in_sub, e.g. `{title}/Season {season:02}`,
+    // `{title}/{resolution}` to keep qualities apart, `{title:first_letter}/{title}` for
+    // alphabetical buckets or `{year}/{broadcast_season}/{title}` for an archive by air date
+    // (broadcast_season is Winter to Fall, from `Fall 2023` or a date in the name). Segments whose
+    // placeholder wasn't parsed are left out. Defaults to `{title}`
+    pub(crate) folder_template: Option<String>,
+    // New file name (extension kept) for copied files, e.g. `{title} - {episode:02}`. Parts in
+    // `<>` are left out when one of their placeholders wasn't parsed, so with place_in_sub off
+    // `{title}< - S{season:02}>< - E{episode:02}>< [{resolution}]>` gives a flat library of
+    // descriptive names. Without a section, a missing value keeps the original name
+    pub(crate) rename_template: Option<String>,
+    // Start copying as soon as the writer closes the file (inotify IN_CLOSE_WRITE) instead of
+    // waiting for the next poll. Backends that never emit it keep the polling behaviour
+    #[serde(default)]
+    pub(crate) close_write_events: bool,
+    // Copy only: keep the file's folders below the source instead of the title folder, like Sync
+    // but without ever deleting
+    #[serde(default)]
+    pub(crate) preserve_structure: bool,
+    // Copy only: organize what is already in the source at startup. Entries not modified since
+    // the previous startup scan are skipped, which needs state_file to survive restarts
+    #[serde(default)]
+    pub(crate) process_existing: bool,
+    // Remove the source once it has been copied successfully
+    #[serde(default)]
+    pub(crate) delete_source: bool,
+    // With delete_source, wait this long after the copy before removing the source
+    pub(crate) delete_after_secs: Option<u64>,
+    // What to do when an identical file (by content hash) already exists in the destination
+    #[serde(default)]
+    pub(crate) dedup: DedupPolicy,
+    // Extensions (e.g. `srt`, `ass`, `jpg`) of files that follow the video sharing their basename
+    #[serde(default)]
+    pub(crate) sidecar_extensions: Vec<String>,
+    // How long movers wait between checks that a download has finished
+    pub(crate) wait_time_secs: Option<u64>,
+    // Upper bound on copies running at once for this path, unlimited when unset
+    pub(crate) max_concurrent: Option<usize>,
+    // What to do when the destination file already exists
+    pub(crate) on_conflict: Option<ConflictPolicy>,
+    // With Overwrite, the replaced file is kept as `name.mkv.bak` instead of being destroyed,
+    // older ones as `name.mkv.bak.1` and up, at most this many in total
+    pub(crate) backup_count: Option<usize>,
+    // Parsed release types (matched case-insensitively) mapped to a folder inside the title
+    // folder, e.g. `{ NCOP = "Extras", NCED = "Extras", Special = "Specials" }`
+    #[serde(default)]
+    pub(crate) extras_folders: HashMap<String, String>,
+    // Parsed language or audio terms (matched case-insensitively) mapped to a separate tree. A
+    // relative path is a folder inside the destination, an absolute one replaces it, e.g.
+    // `{ "Dual Audio" = "Dub", English = "/media/anime-dub" }`
+    #[serde(default)]
+    pub(crate) language_folders: HashMap<String, PathBuf>,
+    // Parsed subtitle terms (matched case-insensitively) mapped to a separate tree, applied below
+    // the language folder: relative is a folder inside it, absolute replaces it, e.g.
+    // `{ Hardsubs = "Hardsubbed" }`
+    #[serde(default)]
+    pub(crate) subtitle_folders: HashMap<String, PathBuf>,
+    // Used the same way when no subtitle term in subtitle_folders was parsed
+    pub(crate) default_subtitle_folder: Option<PathBuf>,
+    // Filesystem events buffered for the watcher. Larger values absorb bigger bursts (e.g.
+    // unpacking a season) at the cost of memory. Once it is full the notify thread waits briefly,
+    // then drops events and the watcher rescans its source instead: a burst costs a scan rather
+    // than unbounded memory or a notify thread stuck behind the watcher
+    pub(crate) channel_capacity: Option<usize>,
+    // When a detected folder only wraps a single video, organize the video instead of the folder
+    #[serde(default)]
+    pub(crate) flatten_single_file_dirs: bool,
+    // Regex rules tried in order against the file name before anitomy, for naming schemes it
+    // gets wrong. The first matching rule supplies the title, episode and season
+    #[serde(default)]
+    pub(crate) title_rules: Vec<TitleRule>,
+    // Leave dotfiles and dot-folders where they are
+    #[serde(default = "default_skip_hidden")]
+    pub(crate) skip_hidden: bool,
+    // File or folder names never copied or synced, on top of the built-in JUNK_NAMES
+    #[serde(default)]
+    pub(crate) junk_names: Vec<String>,
+    // For airing shows: after organizing an episode, remove older episodes of the same title and
+    // season from its folder so only this many of the newest remain
+    pub(crate) keep_latest: Option<usize>,
+    // Log removals (pruning, sync deletions, delete_source) instead of performing them
+    #[serde(default)]
+    pub(crate) dry_run: bool,
+    // Copies run at once during the initial Sync reconciliation, deletions always run one by one
+    pub(crate) sync_workers: Option<usize>,
+    // Shorten destination folder and file names that would exceed the platform's length limits,
+    // keeping the extension and adding a short hash. Without it such copies fail
+    #[serde(default)]
+    pub(crate) truncate_long_paths: bool,
+    // Trim trailing dots and spaces from destination names on every platform, not only Windows,
+    // e.g. for libraries on a drive that is also mounted by Windows machines
+    #[serde(default)]
+    pub(crate) trim_trailing_dots: bool,
+    // Log copy progress every this many seconds and/or every this many percent
+    pub(crate) progress_secs: Option<u64>,
+    pub(crate) progress_percent: Option<u64>,
+    // Tried in order against the file name, the first match replaces destination as the library
+    // root, e.g. `{ pattern = '\b1080p\b', destination = "/media/hd" }`
+    #[serde(default)]
+    pub(crate) destination_rules: Vec<DestinationRule>,
+    // With all three set, files at most hot_max_age_days old go to destination_hot and older ones
+    // to destination_cold, e.g. the current season on a fast drive and the back catalog on a slow
+    // one. Files a destination rule matches, and files whose age can't be told, use destination
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) destination_hot: Option<PathBuf>,
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) destination_cold: Option<PathBuf>,
+    pub(crate) hot_max_age_days: Option<u64>,
+    // What a file's age is measured from, the other one when that isn't available
+    #[serde(default)]
+    pub(crate) age_from: AgeSource,
+    // Unix permissions (e.g. `0o664` and `0o775`) set on copied files and created folders
+    pub(crate) file_mode: Option<u32>,
+    pub(crate) dir_mode: Option<u32>,
+    // Sync only: rerun the full reconciliation this often to catch events the backend dropped
+    pub(crate) resync_interval_secs: Option<u64>,
+    // How file contents get to the destination
+    #[serde(default)]
+    pub(crate) link_mode: LinkMode,
+    // Stop processing events for this path after this many failures in a row. Other paths keep
+    // running, and `resume <name>` on the control socket picks it back up
+    pub(crate) pause_after_errors: Option<u64>,
+    // Record the source path and time on each organized file as `user.aorg.*` xattrs (Unix only)
+    #[serde(default)]
+    pub(crate) write_provenance_xattr: bool,
+    // Paths start in descending priority (default 0): a path's startup pass (the initial sync of a
+    // Sync path, the sort of files already in a Sort path) only begins once every higher-priority
+    // path has finished its own. After startup, events from all paths are handled as they come in
+    #[serde(default)]
+    pub(crate) priority: i32,
+    // Empty file (e.g. `.complete`) written into the destination folder once a move finishes, so
+    // downstream tools know the folder is fully populated. Removed again when the next move starts
+    pub(crate) complete_marker: Option<String>,
+    // Read/write buffer for copies. Larger buffers help fast disks and networks, smaller ones
+    // save memory (one buffer per copy in flight). Setting it copies through the buffer even
+    // without progress logging, instead of std::fs::copy. Defaults to 1 MiB, at least 4 KiB
+    pub(crate) copy_buffer_bytes: Option<usize>,
+    // Linux only: tell the kernel to drop what copies read and wrote from the page cache as they
+    // go, so a large batch doesn't push out everything else a busy server had cached. Copies
+    // through the buffer like copy_buffer_bytes, and flushes the destination every 64 MiB
+    #[serde(default)]
+    pub(crate) avoid_cache: bool,
+    // Program and arguments deciding the folder a file goes in, e.g. `["python3", "route.py"]`.
+    // It gets the file name and anitomy elements as JSON on stdin and prints the folder relative
+    // to the destination root. When it fails or times out the built-in layout is used
+    pub(crate) destination_resolver_command: Option<Vec<String>>,
+    pub(crate) destination_resolver_timeout_secs: Option<u64>,
+    // Files that can't be organized are moved here with a `<name>.error.txt` saying why: Copy
+    // paths after max_attempts failed copies (default 3), Sort paths after a failed rename.
+    // Without it they stay in the source and are retried
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) quarantine_dir: Option<PathBuf>,
+    // Before copying a file whose name carries a CRC32 (`[ABCD1234]`), check the contents against
+    // it. Corrupt downloads are skipped, or moved to quarantine_dir when set
+    #[serde(default)]
+    pub(crate) verify_crc: bool,
+    pub(crate) max_attempts: Option<u32>,
+    // Filesystem events the watcher reacts to. Default: Create and Remove for Sync, Create for
+    // Copy and Sort. Modify re-copies (Sync) or picks up (Copy, Sort) changed files, Rename treats
+    // a file renamed into the source as new and, for Sync, one renamed away as removed
+    pub(crate) events: Option<Vec<WatchedEvent>>,
+    // Mount the source lives on, e.g. `/mnt/downloads` for an SMB share. While it isn't mounted
+    // the path waits (at startup) or ignores events and resyncs instead of seeing an empty source
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) mount_point: Option<PathBuf>,
+    // Credentials for an s3:// destination, the AWS_* variables for anything left out
+    #[serde(default)]
+    pub(crate) s3: S3Credentials,
+    // Run the organized file through ffmpeg (Copy only) and replace it with the result
+    pub(crate) post_move_transcode: Option<TranscodeConfig>,
+    // Give up on a single copy after this long and retry it (counting towards max_attempts),
+    // rather than letting a hung destination mount hold a max_concurrent slot forever
+    pub(crate) copy_timeout_secs: Option<u64>,
+    // Copy only: a file is only ready once it went this long without a write, for download
+    // clients that create the final file empty and fill it in place instead of using `.partial`
+    pub(crate) settle_secs: Option<u64>,
+    // Copy and Sort: remove empty folders from the destination this often, off when unset
+    pub(crate) cleanup_empty_dirs_secs: Option<u64>,
+    // Sync only: sleep this long before the initial sync, for mounts that come up after the daemon
+    pub(crate) startup_delay_secs: Option<u64>,
+    // Sync only: before the initial sync, wait up to this long for the source to have any entry,
+    // then sync anyway. An empty source at startup is more likely a mount that isn't ready than
+    // a library that was deleted
+    pub(crate) wait_for_source_secs: Option<u64>,
+    // Poll the source this often when the native watcher runs out of watches (inotify's
+    // max_user_watches on a large tree). Polling walks the whole tree each time, so keep it long
+    pub(crate) poll_fallback_secs: Option<u64>,
+    // Skip a video (and its sidecars) when the same folder holds a higher release version of the
+    // same episode, so only the `v2` of a batch containing both is organized
+    #[serde(default)]
+    pub(crate) prefer_latest_version: bool,
+    // Elements that must all have been parsed before place_in_sub creates a title folder, a list
+    // inside the list means any one of them, e.g. `["Title", ["Episode", "Type"]]`. Files that
+    // fall short stay in the destination root for review
+    #[serde(default)]
+    pub(crate) required_elements: Vec<RequiredElement>,
+    // How titles are read from names that no title rule matches
+    #[serde(default)]
+    pub(crate) parser: ParserKind,
+    // Tuning for the Anitomy parser, anitomy's own defaults for anything left out
+    #[serde(default)]
+    pub(crate) anitomy: AnitomyOptions,
+    // Release groups (matched case-insensitively) whose files are skipped, e.g. machine-translated
+    // rips
+    #[serde(default)]
+    pub(crate) blocked_groups: Vec<String>,
+    // When set, only files from these release groups are organized, which skips files without a
+    // parsed group too
+    #[serde(default)]
+    pub(crate) allowed_groups: Vec<String>,
+    // With place_in_sub, batch releases (an episode range such as `Show 01-12`) go into this
+    // folder inside the title folder, e.g. `Batch`
+    pub(crate) batch_folder: Option<String>,
+    // With place_in_sub, a title folder that doesn't exist yet is swapped for the most similar
+    // existing sibling at or above this similarity (0 to 1, case-insensitive), so `Spy × Family`
+    // joins `Spy x Family`. Around 0.9 catches such variants without merging different sequels
+    pub(crate) fuzzy_title_threshold: Option<f64>,
+    // Order files detected together (a batch dropped at once, or the existing files) are handed
+    // to movers in
+    #[serde(default)]
+    pub(crate) copy_order: CopyOrder,
+    // What happens to files inside a folder that is being organized when they get events of
+    // their own, e.g. from a single notify event carrying the folder and its children
+    #[serde(default)]
+    pub(crate) nested_events: NestedEvents,
+    // Only these files (relative to the source, e.g. a curated folder of symlinks) are watched
+    // and organized instead of everything below it. The source is only watched recursively when
+    // one of them is in a folder below it. A Copy source that is a file is taken as its folder
+    // with only that file listed
+    #[serde(default)]
+    pub(crate) files: Vec<PathBuf>,
+    // The glob source this path's folder matched, for paths expanded from one
+    #[serde(skip)]
+    pub(crate) glob_source: Option<String>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Defaults {
+    pub(crate) wait_time_secs: Option<u64>,
+    pub(crate) max_concurrent: Option<usize>,
+    pub(crate) on_conflict: Option<ConflictPolicy>,
+    pub(crate) startup_delay_secs: Option<u64>,
+    pub(crate) wait_for_source_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum WatcherTypeConfig {
+    // Adds files from source to dest, removes files not present in source from dest, then watches source for further changes
+    Sync,
+    // Watches source and copies files to dest
+    Copy,
+    // Moves unsorted files in the root of source (normally the same as dest) into their folders
+    Sort,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DedupPolicy {
+    // Always copy
+    #[default]
+    Off,
+    // Leave the duplicate in the source and copy nothing
+    Skip,
+    // Hardlink the destination to the existing identical file instead of copying
+    Hardlink,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LinkMode {
+    // Plain byte copy
+    #[default]
+    Copy,
+    // Copy-on-write clone on btrfs/XFS/APFS: instant and shares blocks, but still a separate file.
+    // Falls back to a plain copy where the filesystem can't
+    Reflink,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum WatchedEvent {
+    Create,
+    // Sync only
+    Remove,
+    Modify,
+    Rename,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MetadataElement {
+    Title,
+    Episode,
+    Season,
+    Type,
+    Resolution,
+    Version,
+    Language,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum RequiredElement {
+    One(MetadataElement),
+    AnyOf(Vec<MetadataElement>),
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ParserKind {
+    // Anime release names
+    #[default]
+    Anitomy,
+    // The file name without its extension is the title, e.g. for music or films
+    Passthrough,
+    // Only title_rules, names none of them match stay in the destination root
+    Regex,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum CopyOrder {
+    #[default]
+    AsDetected,
+    // Subtitles and short episodes first instead of behind a large file
+    SmallestFirst,
+    // By file name, which for most releases is episode order
+    Alphabetical,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AgeSource {
+    // When the file was last modified, usually when the download finished
+    #[default]
+    Modified,
+    // The parsed year, counted from the end of it so anything from this year is new
+    Year,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum NestedEvents {
+    // The folder's mover takes them along, so they aren't copied a second time
+    #[default]
+    Parent,
+    // Each is organized on its own as well, for sources where folders are only containers
+    Separate,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TranscodeConfig {
+    // Arguments between the input and the output file, e.g.
+    // `["-map", "0", "-c:v", "libx265", "-crf", "22", "-c:a", "copy", "-c:s", "copy"]`
+    pub(crate) args: Vec<String>,
+    // Extensions (matched case-insensitively) of the files that are transcoded, e.g. `["mkv"]`
+    pub(crate) extensions: Vec<String>,
+    // Defaults to `ffmpeg` on the PATH
+    pub(crate) program: Option<String>,
+}
+
+// Each secret can be given inline or, to keep it out of the config (e.g. a Docker secret), as a
+// `*_file` whose contents are read at startup, surrounding whitespace trimmed. Secrets are never
+// printed back, verify-config leaves them out
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+pub struct S3Credentials {
+    #[serde(default, skip_serializing)]
+    pub(crate) access_key_id: Option<String>,
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) access_key_id_file: Option<PathBuf>,
+    #[serde(default, skip_serializing)]
+    pub(crate) secret_access_key: Option<String>,
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) secret_access_key_file: Option<PathBuf>,
+    #[serde(default, skip_serializing)]
+    pub(crate) session_token: Option<String>,
+    #[serde(default, deserialize_with = "expand_optional_path")]
+    pub(crate) session_token_file: Option<PathBuf>,
+}
+
+impl S3Credentials {
+    fn secrets(&mut self) -> [(&'static str, &mut Option<String>, &mut Option<PathBuf>); 3] {
+        [
+            (
+                "access_key_id",
+                &mut self.access_key_id,
+                &mut self.access_key_id_file,
+            ),
+            (
+                "secret_access_key",
+                &mut self.secret_access_key,
+                &mut self.secret_access_key_file,
+            ),
+            (
+                "session_token",
+                &mut self.session_token,
+                &mut self.session_token_file,
+            ),
+        ]
+    }
+}
+
+// Which elements anitomy looks for. Turning one off leaves its text to the neighbouring elements,
+// mostly the title
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AnitomyOptions {
+    // Text after the episode number. Off keeps names like `Show - 01 - Part 2` from losing the
+    // part to an episode title, but a title after the number then ends up nowhere
+    pub(crate) episode_title: Option<bool>,
+    // The bracketed group, `[Group]`. Off for sources that put the title in the first brackets
+    pub(crate) release_group: Option<bool>,
+    // `S2`, `2nd Season`. Off keeps the season in the title, so each season gets its own folder
+    pub(crate) season: Option<bool>,
+    // `(2019)`. Off keeps the year in the title, for remakes that share a name
+    pub(crate) year: Option<bool>,
+    pub(crate) video_resolution: Option<bool>,
+    pub(crate) file_extension: Option<bool>,
+    // The CRC32, `[ABCD1234]`
+    pub(crate) file_checksum: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ConflictPolicy {
+    // Replace the existing file
+    #[default]
+    Overwrite,
+    // Keep the existing file and don't copy
+    Skip,
+    // Copy under a free name such as `Show - 01 (1).mkv`
+    Rename,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct TitleRule {
+    pub(crate) pattern: Pattern,
+    pub(crate) title_group: CaptureGroup,
+    pub(crate) episode_group: Option<CaptureGroup>,
+    pub(crate) season_group: Option<CaptureGroup>,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct DestinationRule {
+    pub(crate) pattern: Pattern,
+    #[serde(deserialize_with = "expand_path")]
+    pub(crate) destination: PathBuf,
+}
+
+// A capture group referenced by index (`1`) or by name (`"title"` for `(?<title>...)`)
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum CaptureGroup {
+    Index(usize),
+    Name(String),
+}
+
+impl CaptureGroup {
+    fn exists_in(&self, regex: &Regex) -> bool {
+        match self {
+            CaptureGroup::Index(index) => *index < regex.captures_len(),
+            CaptureGroup::Name(name) => regex.capture_names().flatten().any(|group| group == name),
+        }
+    }
+
+    pub(crate) fn get<'h>(&self, captures: &regex::Captures<'h>) -> Option<&'h str> {
+        match self {
+            CaptureGroup::Index(index) => captures.get(*index),
+            CaptureGroup::Name(name) => captures.name(name),
+        }
+        .map(|group| group.as_str())
+    }
+}
+
+impl std::fmt::Display for CaptureGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureGroup::Index(index) => write!(f, "{index}"),
+            CaptureGroup::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+// Compiled once when the config is loaded, serialized back as its source text
+#[derive(Clone)]
+pub struct Pattern(pub(crate) Regex);
+
+// Equal when the source text is, which is what tells a reloaded config apart
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(Pattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Config {
+    pub async fn load(path: &Path) -> Result<Self, OrganizerError> {
+        let config_file = tokio::fs::read_to_string(path).await.map_err(|e| {
+            OrganizerError::Config(anyhow!("could not read {}: {e}", path.display()))
+        })?;
+        Self::from_toml(&config_file, path)
+    }
+
+    // `path` is where the text is (or is going to be) saved, relative paths in it are resolved
+    // against its folder
+    pub(crate) fn from_toml(config_file: &str, path: &Path) -> Result<Self, OrganizerError> {
+        let mut config: Config =
+            toml::from_str(config_file).map_err(|source| OrganizerError::Parse {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        config.apply_defaults();
+        config
+            .resolve_paths(path.parent().unwrap_or(Path::new(".")))
+            .and_then(|_| config.read_secrets())
+            .and_then(|_| config.expand_sources())
+            .and_then(|_| config.resolve_files())
+            .map(|_| config.adopt_file_sources())
+            .and_then(|_| config.validate())
+            .map_err(OrganizerError::Config)?;
+        Ok(config)
+    }
+
+    // A single path from `AORG_*` variables, for containers without a config file.
+    // `AORG_<OPTION>` sets that path option, e.g. `AORG_SOURCE`, `AORG_DEST` (or
+    // `AORG_DESTINATION`), `AORG_WATCHER_TYPE` and `AORG_PLACE_IN_SUB`. Values are read as TOML
+    // where they parse (`true`, `5`, `["mkv"]`) and as strings otherwise. None without
+    // `AORG_SOURCE`
+    pub fn from_env() -> Result<Option<Self>, anyhow::Error> {
+        let mut path = toml::Table::new();
+        for (key, value) in std::env::vars() {
+            let Some(option) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let option = match option.to_lowercase() {
+                option if option == "dest" => "destination".to_string(),
+                option => option,
+            };
+            let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or(toml::Value::String(value));
+            path.insert(option, value);
+        }
+
+        if !path.contains_key("source") {
+            return Ok(None);
+        }
+        let defaults = [
+            ("name", toml::Value::from("env")),
+            ("watcher_type", toml::Value::from("Copy")),
+            ("place_in_sub", toml::Value::from(true)),
+        ];
+        for (option, value) in defaults {
+            path.entry(option).or_insert(value);
+        }
+
+        let mut table = toml::Table::new();
+        table.insert("paths".to_string(), toml::Value::Array(vec![path.into()]));
+        let mut config: Config = toml::Value::Table(table).try_into()?;
+        config.apply_defaults();
+        config.resolve_paths(&std::env::current_dir()?)?;
+        config.read_secrets()?;
+        config.expand_sources()?;
+        config.resolve_files()?;
+        config.adopt_file_sources();
+        config.validate()?;
+        Ok(Some(config))
+    }
+
+    pub fn paths(&self) -> &[PathConfig] {
+        &self.paths
+    }
+
+    pub(crate) fn heartbeat(&self) -> Option<Duration> {
+        self.heartbeat_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.shutdown_timeout_secs
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+        )
+    }
+
+    // Fills in everything a path left out, first from the defaults block, then built-in values
+    fn apply_defaults(&mut self) {
+        for path in &mut self.paths {
+            path.wait_time_secs = path
+                .wait_time_secs
+                .or(self.defaults.wait_time_secs)
+                .or(Some(DEFAULT_WAIT_TIME_SECS));
+            path.max_concurrent = path.max_concurrent.or(self.defaults.max_concurrent);
+            path.on_conflict = path
+                .on_conflict
+                .or(self.defaults.on_conflict)
+                .or(Some(ConflictPolicy::default()));
+            path.startup_delay_secs = path.startup_delay_secs.or(self.defaults.startup_delay_secs);
+            path.wait_for_source_secs = path
+                .wait_for_source_secs
+                .or(self.defaults.wait_for_source_secs);
+            path.channel_capacity = path.channel_capacity.or(Some(DEFAULT_CHANNEL_CAPACITY));
+            path.sync_workers = path.sync_workers.or(Some(DEFAULT_SYNC_WORKERS));
+        }
+    }
+
+    // Relative paths are relative to the directory holding the config file
+    fn resolve_paths(&mut self, base: &Path) -> Result<(), anyhow::Error> {
+        let resolve = |path: &Path| std::path::absolute(base.join(path));
+
+        if let Some(state_file) = &self.state_file {
+            self.state_file = Some(resolve(state_file)?);
+        }
+
+        if let Some(control_socket) = &self.control_socket {
+            self.control_socket = Some(resolve(control_socket)?);
+        }
+
+        if let Some(unparsed_log) = &self.unparsed_log {
+            self.unparsed_log = Some(resolve(unparsed_log)?);
+        }
+
+        if let Some(log) = &mut self.log {
+            log.directory = resolve(&log.directory)?;
+        }
+
+        for path in &mut self.paths {
+            path.source = resolve(&path.source)?;
+            // Left empty for validate, resolved it would be the config's folder
+            if !path.has_remote_destination() && !path.destination.as_os_str().is_empty() {
+                path.destination = resolve(&path.destination)?;
+            }
+            for rule in &mut path.destination_rules {
+                rule.destination = resolve(&rule.destination)?;
+            }
+            if let Some(quarantine_dir) = &path.quarantine_dir {
+                path.quarantine_dir = Some(resolve(quarantine_dir)?);
+            }
+            if let Some(mount_point) = &path.mount_point {
+                path.mount_point = Some(resolve(mount_point)?);
+            }
+            for (_, _, file) in path.s3.secrets() {
+                if let Some(secret_file) = file {
+                    *file = Some(resolve(secret_file)?);
+                }
+            }
+            if let Some(destination_hot) = &path.destination_hot {
+                path.destination_hot = Some(resolve(destination_hot)?);
+            }
+            if let Some(destination_cold) = &path.destination_cold {
+                path.destination_cold = Some(resolve(destination_cold)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    // After expand_sources, so relative files are taken from each folder a glob source matched
+    // rather than from the pattern
+    fn resolve_files(&mut self) -> Result<(), anyhow::Error> {
+        for path in &mut self.paths {
+            for file in &mut path.files {
+                *file = std::path::absolute(path.source.join(&file))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Fills in the `*_file` secrets. Giving a secret both ways is refused rather than guessed at
+    fn read_secrets(&mut self) -> Result<(), anyhow::Error> {
+        let mut problems = Vec::new();
+        for path in &mut self.paths {
+            let name = &path.name;
+            for (secret, value, file) in path.s3.secrets() {
+                let Some(secret_file) = file.as_ref() else {
+                    continue;
+                };
+                if value.is_some() {
+                    problems.push(format!(
+                        "{name}: s3 {secret} and {secret}_file are both set, use only one"
+                    ));
+                    continue;
+                }
+                match std::fs::read_to_string(secret_file) {
+                    Ok(contents) => *value = Some(contents.trim().to_string()),
+                    Err(e) => problems.push(format!(
+                        "{name}: could not read s3 {secret}_file {}: {e}",
+                        secret_file.display()
+                    )),
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid config: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+
+    // A Copy path whose source is a single file watches the folder it is in for only that file,
+    // the same as listing it in `files`. Other paths are told off in validate
+    fn adopt_file_sources(&mut self) {
+        for path in &mut self.paths {
+            if !path.source.is_file()
+                || !path.files.is_empty()
+                || !matches!(path.watcher_type, WatcherTypeConfig::Copy)
+            {
+                continue;
+            }
+            let Some(parent) = path.source.parent() else {
+                continue;
+            };
+
+            let file = std::mem::replace(&mut path.source, parent.to_path_buf());
+            path.files.push(file);
+        }
+    }
+
+    // A source such as `/downloads/{a,b}/*/anime` becomes one path per matching folder, named
+    // after the path and the folder. `[` and `]` are literal since release names are full of them.
+    // Folders are matched when the config is loaded, and again on reload, which starts and stops
+    // the watchers of folders that began or stopped matching (see Organizer::run)
+    fn expand_sources(&mut self) -> Result<(), anyhow::Error> {
+        let mut paths = Vec::new();
+        for path in std::mem::take(&mut self.paths) {
+            let source = path.source.to_string_lossy().into_owned();
+            if !source.contains(['*', '?', '{']) {
+                paths.push(path);
+                continue;
+            }
+
+            let mut sources = Vec::new();
+            for pattern in expand_braces(&source) {
+                for entry in glob::glob(&escape_brackets(&pattern))? {
+                    let entry = entry?;
+                    if entry.is_dir() {
+                        sources.push(entry);
+                    }
+                }
+            }
+            sources.sort();
+            sources.dedup();
+
+            if sources.is_empty() {
+                warn!("{}: no folder matches the source {source}", path.name);
+            }
+            let glob_source = Some(source);
+
+            for source in sources {
+                // Absolute files only belong to the folder they are in, a folder with none of
+                // them isn't watched at all rather than for everything
+                let files: Vec<PathBuf> = path
+                    .files
+                    .iter()
+                    .filter(|file| file.is_relative() || file.starts_with(&source))
+                    .cloned()
+                    .collect();
+                if files.is_empty() && !path.files.is_empty() {
+                    continue;
+                }
+                paths.push(PathConfig {
+                    name: format!("{} ({})", path.name, source.display()),
+                    source,
+                    files,
+                    glob_source: glob_source.clone(),
+                    ..path.clone()
+                });
+            }
+        }
+
+        self.paths = paths;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let mut problems = Vec::new();
+        let mut names = HashSet::new();
+
+        if self
+            .log
+            .as_ref()
+            .is_some_and(|log| log.max_files == Some(0))
+        {
+            problems.push("log max_files must be at least 1".to_string());
+        }
+
+        if self.runtime.worker_threads == Some(0) || self.runtime.max_blocking_threads == Some(0) {
+            problems.push(
+                "runtime worker_threads and max_blocking_threads must be at least 1".to_string(),
+            );
+        }
+
+        for path in &self.paths {
+            if path.name.trim().is_empty() {
+                problems.push("a path has an empty name".to_string());
+            } else if !names.insert(path.name.as_str()) {
+                problems.push(format!(
+                    "the name {} is used by more than one path",
+                    path.name
+                ));
+            }
+
+            // Also through symlinks, where a copy would truncate files onto themselves
+            let same_folder = path.source == path.destination
+                || matches!(
+                    (path.source.canonicalize(), path.destination.canonicalize()),
+                    (Ok(source), Ok(destination)) if source == destination
+                );
+            if same_folder && !matches!(path.watcher_type, WatcherTypeConfig::Sort) {
+                problems.push(format!(
+                    "{}: source and destination are the same, which only Sort supports",
+                    path.name
+                ));
+            } else if matches!(path.watcher_type, WatcherTypeConfig::Sync)
+                && let Some(problem) = path.sync_destination_problem()
+            {
+                problems.push(format!(
+                    "{}: destination {} {problem}, syncing would delete from it",
+                    path.name,
+                    path.destination.display()
+                ));
+            } else if path.destination.as_os_str().is_empty() {
+                problems.push(format!("{}: destination is empty", path.name));
+            }
+
+            if path
+                .fuzzy_title_threshold
+                .is_some_and(|threshold| !(0.0..=1.0).contains(&threshold))
+            {
+                problems.push(format!(
+                    "{}: fuzzy_title_threshold must be between 0 and 1",
+                    path.name
+                ));
+            }
+
+            let age_options = [
+                path.destination_hot.is_some(),
+                path.destination_cold.is_some(),
+                path.hot_max_age_days.is_some(),
+            ];
+            if age_options.contains(&true) && age_options.contains(&false) {
+                problems.push(format!(
+                    "{}: destination_hot, destination_cold and hot_max_age_days only work together",
+                    path.name
+                ));
+            }
+
+            if path.source.is_file() {
+                problems.push(format!(
+                    "{}: source {} is a file, only Copy paths without files can watch a single file",
+                    path.name,
+                    path.source.display()
+                ));
+            }
+
+            if !path.files.is_empty() {
+                if !matches!(path.watcher_type, WatcherTypeConfig::Copy) {
+                    problems.push(format!(
+                        "{}: files is only supported by Copy paths",
+                        path.name
+                    ));
+                }
+                for file in path
+                    .files
+                    .iter()
+                    .filter(|file| !file.starts_with(&path.source))
+                {
+                    problems.push(format!(
+                        "{}: {} is not inside the source",
+                        path.name,
+                        file.display()
+                    ));
+                }
+            }
+
+            if path.max_concurrent == Some(0) {
+                problems.push(format!("{}: max_concurrent must be at least 1", path.name));
+            }
+
+            if path.has_remote_destination() {
+                if !matches!(path.watcher_type, WatcherTypeConfig::Copy) {
+                    problems.push(format!(
+                        "{}: {} destinations are only supported by Copy paths",
+                        path.name,
+                        remote::SCHEME
+                    ));
+                }
+                // These write to the destination directly rather than through the object store
+                let unsupported = [
+                    ("keep_latest", path.keep_latest.is_some()),
+                    ("dedup", path.dedup != DedupPolicy::Off),
+                    ("file_mode", path.file_mode.is_some()),
+                    ("dir_mode", path.dir_mode.is_some()),
+                    ("write_provenance_xattr", path.write_provenance_xattr),
+                    ("complete_marker", path.complete_marker.is_some()),
+                    ("post_move_transcode", path.post_move_transcode.is_some()),
+                    (
+                        "cleanup_empty_dirs_secs",
+                        path.cleanup_empty_dirs_secs.is_some(),
+                    ),
+                ];
+                for (option, _) in unsupported.iter().filter(|(_, set)| *set) {
+                    problems.push(format!(
+                        "{}: {option} is not supported with a {} destination",
+                        path.name,
+                        remote::SCHEME
+                    ));
+                }
+            }
+
+            if path.progress_secs == Some(0) || path.progress_percent == Some(0) {
+                problems.push(format!(
+                    "{}: progress_secs and progress_percent must be at least 1",
+                    path.name
+                ));
+            }
+
+            if path.pause_after_errors == Some(0) {
+                problems.push(format!(
+                    "{}: pause_after_errors must be at least 1",
+                    path.name
+                ));
+            }
+
+            if !matches!(path.watcher_type, WatcherTypeConfig::Sync)
+                && path.watched_events().contains(&WatchedEvent::Remove)
+            {
+                problems.push(format!(
+                    "{}: only Sync paths react to Remove events",
+                    path.name
+                ));
+            }
+
+            if path.max_attempts == Some(0) {
+                problems.push(format!("{}: max_attempts must be at least 1", path.name));
+            }
+
+            if path.sync_workers == Some(0) {
+                problems.push(format!("{}: sync_workers must be at least 1", path.name));
+            }
+
+            if path.keep_latest == Some(0) {
+                problems.push(format!("{}: keep_latest must be at least 1", path.name));
+            }
+
+            if path.channel_capacity == Some(0) {
+                problems.push(format!(
+                    "{}: channel_capacity must be at least 1",
+                    path.name
+                ));
+            }
+
+            if path
+                .copy_buffer_bytes
+                .is_some_and(|bytes| bytes < MIN_COPY_BUFFER_BYTES)
+            {
+                problems.push(format!(
+                    "{}: copy_buffer_bytes must be at least {MIN_COPY_BUFFER_BYTES}",
+                    path.name
+                ));
+            }
+
+            if path
+                .destination_resolver_command
+                .as_ref()
+                .is_some_and(|command| command.is_empty())
+            {
+                problems.push(format!(
+                    "{}: destination_resolver_command needs at least a program",
+                    path.name
+                ));
+            }
+
+            if path
+                .post_move_transcode
+                .as_ref()
+                .is_some_and(|transcode| transcode.extensions.is_empty())
+            {
+                problems.push(format!(
+                    "{}: post_move_transcode needs at least one extension",
+                    path.name
+                ));
+            }
+
+            if matches!(path.parser, ParserKind::Regex) && path.title_rules.is_empty() {
+                problems.push(format!(
+                    "{}: the Regex parser needs at least one title rule",
+                    path.name
+                ));
+            }
+
+            if let Some(marker) = &path.complete_marker
+                && (marker.is_empty() || marker.contains(['/', '\\']))
+            {
+                problems.push(format!(
+                    "{}: complete_marker must be a file name, not {marker:?}",
+                    path.name
+                ));
+            }
+
+            for rule in &path.title_rules {
+                let groups = [
+                    Some(&rule.title_group),
+                    rule.episode_group.as_ref(),
+                    rule.season_group.as_ref(),
+                ];
+                for group in groups.into_iter().flatten() {
+                    if !group.exists_in(&rule.pattern.0) {
+                        problems.push(format!(
+                            "{}: title rule {} has no capture group {group}",
+                            path.name,
+                            rule.pattern.0.as_str()
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid config: {}", problems.join("; "));
+        }
+
+        Ok(())
+    }
+}
+
+impl PathConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    // Sync deletes whatever the source doesn't have from the destination, so it has to be a
+    // folder of its own. None when it is
+    pub(crate) fn sync_destination_problem(&self) -> Option<&'static str> {
+        if self.destination.as_os_str().is_empty() {
+            return Some("is empty");
+        }
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let (source, destination) = (canonical(&self.source), canonical(&self.destination));
+        if destination.parent().is_none() {
+            Some("is the root of a filesystem")
+        } else if destination == source {
+            Some("is the source")
+        } else if destination.starts_with(&source) {
+            Some("is inside the source")
+        } else if source.starts_with(&destination) {
+            Some("contains the source")
+        } else {
+            None
+        }
+    }
+
+    // An object store bucket rather than a folder, see remote::ObjectStoreFileSystem
+    pub(crate) fn has_remote_destination(&self) -> bool {
+        self.destination
+            .to_str()
+            .is_some_and(|destination| destination.starts_with(remote::SCHEME))
+    }
+
+    pub fn watcher_type(&self) -> WatcherTypeConfig {
+        self.watcher_type
+    }
+
+    pub fn place_in_sub(&self) -> bool {
+        self.place_in_sub
+    }
+
+    pub fn delete_source(&self) -> bool {
+        self.delete_source
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn link_mode(&self) -> LinkMode {
+        self.link_mode
+    }
+
+    pub fn wait_time(&self) -> Duration {
+        Duration::from_secs(self.wait_time_secs.unwrap_or(DEFAULT_WAIT_TIME_SECS))
+    }
+
+    pub fn on_conflict(&self) -> ConflictPolicy {
+        self.on_conflict.unwrap_or_default()
+    }
+
+    pub(crate) fn backup_count(&self) -> usize {
+        match self.on_conflict() {
+            ConflictPolicy::Overwrite => self.backup_count.unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn channel_capacity(&self) -> usize {
+        self.channel_capacity.unwrap_or(DEFAULT_CHANNEL_CAPACITY)
+    }
+
+    // The library root the file belongs in, from the first matching destination rule
+    pub(crate) fn destination_for(&self, file: &Path) -> &Path {
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+        self.destination_rules
+            .iter()
+            .find(|rule| rule.pattern.0.is_match(&file_name))
+            .map_or(&self.destination, |rule| &rule.destination)
+    }
+
+    // destination_hot and destination_cold, when files are split by age
+    pub(crate) fn age_destinations(&self) -> impl Iterator<Item = &Path> {
+        self.destination_hot
+            .iter()
+            .chain(&self.destination_cold)
+            .map(PathBuf::as_path)
+    }
+
+    pub(crate) fn hot_max_age(&self) -> Option<Duration> {
+        self.hot_max_age_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+    }
+
+    pub(crate) fn resync_interval(&self) -> Option<Duration> {
+        self.resync_interval_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn watched_events(&self) -> Vec<WatchedEvent> {
+        match (&self.events, self.watcher_type) {
+            (Some(events), _) => events.clone(),
+            (None, WatcherTypeConfig::Sync) => vec![WatchedEvent::Create, WatchedEvent::Remove],
+            (None, _) => vec![WatchedEvent::Create],
+        }
+    }
+
+    pub(crate) fn poll_fallback(&self) -> Option<Duration> {
+        self.poll_fallback_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn copy_timeout(&self) -> Option<Duration> {
+        self.copy_timeout_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn settle(&self) -> Option<Duration> {
+        self.settle_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn cleanup_empty_dirs(&self) -> Option<Duration> {
+        self.cleanup_empty_dirs_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn startup_delay(&self) -> Option<Duration> {
+        self.startup_delay_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn wait_for_source(&self) -> Option<Duration> {
+        self.wait_for_source_secs
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS)
+    }
+
+    pub(crate) fn resolver_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.destination_resolver_timeout_secs
+                .unwrap_or(DEFAULT_RESOLVER_TIMEOUT_SECS),
+        )
+    }
+
+    pub(crate) fn copy_options(&self) -> CopyOptions {
+        CopyOptions {
+            link_mode: self.link_mode,
+            buffer_bytes: self.copy_buffer_bytes,
+            avoid_cache: self.avoid_cache,
+        }
+    }
+
+    pub(crate) fn sync_workers(&self) -> usize {
+        self.sync_workers.unwrap_or(DEFAULT_SYNC_WORKERS)
+    }
+
+    // Whether the path is one of `files`, or anything when they aren't set
+    pub(crate) fn is_listed(&self, path: &Path) -> bool {
+        self.files.is_empty() || self.files.iter().any(|file| file == path)
+    }
+
+    // Listed files directly in the source only need the source itself watched
+    pub(crate) fn watch_mode(&self) -> RecursiveMode {
+        let nested = self
+            .files
+            .iter()
+            .any(|file| file.parent() != Some(self.source.as_path()));
+        if self.files.is_empty() || nested {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+
+    // Whether the path or any folder it sits in below the source is hidden or junk
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        match path.strip_prefix(&self.source) {
+            Ok(relative) => relative.components().any(|component| match component {
+                Component::Normal(name) => self.is_ignored_name(name),
+                _ => false,
+            }),
+            // Folders above the source may well be hidden, so only the name itself counts
+            Err(_) => path
+                .file_name()
+                .is_some_and(|name| self.is_ignored_name(name)),
+        }
+    }
+
+    // Whether allowed_groups and blocked_groups let a file from `group` through
+    pub(crate) fn allows_group(&self, group: Option<&str>) -> bool {
+        let listed = |groups: &[String]| {
+            group.is_some_and(|group| {
+                groups
+                    .iter()
+                    .any(|listed| listed.eq_ignore_ascii_case(group))
+            })
+        };
+        !listed(&self.blocked_groups)
+            && (self.allowed_groups.is_empty() || listed(&self.allowed_groups))
+    }
+
+    pub(crate) fn is_ignored_name(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        (self.skip_hidden && name.starts_with('.'))
+            || JUNK_NAMES
+                .iter()
+                .copied()
+                .chain(self.junk_names.iter().map(String::as_str))
+                .any(|junk| junk.eq_ignore_ascii_case(&name))
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+            .unwrap_or(tokio::sync::Semaphore::MAX_PERMITS)
+    }
+}
+
+// `a{b,c{d,e}}f` becomes `abf`, `acdf` and `acef`
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+
+    let mut depth = 0;
+    let mut bounds = vec![open];
+    for (index, c) in pattern[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            ',' if depth == 1 => bounds.push(open + index),
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    bounds.push(open + index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Unbalanced, so taken literally
+    if depth != 0 {
+        return vec![pattern.to_string()];
+    }
+
+    let prefix = &pattern[..open];
+    let suffix = &pattern[bounds[bounds.len() - 1] + 1..];
+    bounds
+        .windows(2)
+        .flat_map(|bound| {
+            let alternative = &pattern[bound[0] + 1..bound[1]];
+            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+        })
+        .collect()
+}
+
+fn escape_brackets(pattern: &str) -> String {
+    pattern
+        .chars()
+        .map(|c| match c {
+            '[' => "[[]".to_string(),
+            ']' => "[]]".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn default_log_file_name() -> String {
+    "anime-organizer.log".to_string()
+}
+
+fn default_skip_hidden() -> bool {
+    true
+}
+
+// Path fields may reference the environment as `$VAR` or `${VAR}`
+fn expand_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    let path = String::deserialize(deserializer)?;
+    expand_env(&path)
+        .map(PathBuf::from)
+        .map_err(serde::de::Error::custom)
+}
+
+fn expand_optional_path<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PathBuf>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|path| expand_env(&path).map(PathBuf::from))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, consumed) = match after.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated ${{ in {value}"))?;
+                (&braced[..end], end + 2)
+            }
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        if name.is_empty() {
+            // A `$` that doesn't start a reference is kept as is
+            expanded.push('$');
+        } else {
+            let variable = std::env::var(name)
+                .map_err(|_| format!("environment variable {name} used in {value} is not set"))?;
+            expanded.push_str(&variable);
+        }
+        rest = &after[consumed..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// An explicit path always wins, otherwise the first existing file from the search list is used
+pub fn find_config_path(explicit: Option<PathBuf>) -> Result<PathBuf, anyhow::Error> {
+    if let Some(path) = explicit {
+        return Ok(path);
+    }
+
+    let candidates = search_paths();
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            let searched = candidates
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow!("No config file found, searched: {searched}")
+        })
+}
+
+// Where a new config goes when none is given: the first place searched
+pub(crate) fn default_config_path() -> PathBuf {
+    search_paths().remove(0)
+}
+
+fn search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(config_home) = non_empty_env("XDG_CONFIG_HOME") {
+        paths.push(config_home.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+    }
+
+    if let Some(home) = non_empty_env("HOME") {
+        paths.push(
+            home.join(".config")
+                .join(CONFIG_DIR_NAME)
+                .join(CONFIG_FILE_NAME),
+        );
+    }
+
+    paths.push(
+        Path::new("/etc")
+            .join(CONFIG_DIR_NAME)
+            .join(CONFIG_FILE_NAME),
+    );
+    paths.push(Path::new(".").join(CONFIG_FILE_NAME));
+
+    paths
+}
+
+fn non_empty_env(key: &str) -> Option<PathBuf> {
+    std::env::var_os(key)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_sources_with_files_load_one_path_per_folder() {
+        let root =
+            std::env::temp_dir().join(format!("anime-organizer-glob-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for folder in ["a", "b"] {
+            std::fs::create_dir_all(root.join(folder).join("anime")).unwrap();
+        }
+        let load = |files: &str| {
+            Config::from_toml(
+                &format!(
+                    r#"
+                    [[paths]]
+                    name = "anime"
+                    watcher_type = "Copy"
+                    source = '{}'
+                    destination = '{}'
+                    place_in_sub = true
+                    files = [{files}]
+                    "#,
+                    root.join("*").join("anime").display(),
+                    root.join("library").display()
+                ),
+                &root.join("config.toml"),
+            )
+        };
+
+        let config = load(r#""Show - 01.mkv""#).unwrap();
+        let files: Vec<_> = config.paths.iter().map(|path| path.files.clone()).collect();
+        assert_eq!(
+            files,
+            [
+                vec![root.join("a/anime/Show - 01.mkv")],
+                vec![root.join("b/anime/Show - 01.mkv")]
+            ]
+        );
+
+        let only_a = format!("'{}'", root.join("a/anime/Show - 01.mkv").display());
+        let config = load(&only_a).unwrap();
+        assert_eq!(config.paths.len(), 1);
+        assert_eq!(config.paths[0].source, root.join("a/anime"));
+        assert_eq!(
+            config.paths[0].glob_source,
+            Some(root.join("*").join("anime").display().to_string())
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
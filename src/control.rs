@@ -0,0 +1,78 @@
+use crate::{rules, status};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{Instrument, error, info};
+
+// Line based: each command gets a reply of one or more lines ending with an empty line
+// `reload` re-reads the rule tables and re-matches glob sources from `config_path`, when the
+// config came from a file
+pub async fn serve(path: PathBuf, config_path: Option<PathBuf>) -> Result<(), anyhow::Error> {
+    // Left behind by a previous run that didn't shut down cleanly
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    info!("Listening for control commands on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let config_path = config_path.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = handle(stream, config_path.as_deref()).await {
+                    error!("Control connection failed: {}", e);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+async fn handle(stream: UnixStream, config_path: Option<&Path>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = execute(line.trim(), config_path).await;
+        writer.write_all(format!("{reply}\n\n").as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+async fn execute(command: &str, config_path: Option<&Path>) -> String {
+    match command.split_once(' ') {
+        Some(("resume", name)) => {
+            if status::resume(name.trim()).await {
+                "ok".to_string()
+            } else {
+                format!("error: {} is not paused", name.trim())
+            }
+        }
+        None if command == "reload" => match config_path {
+            Some(config_path) => rules::reload(config_path)
+                .await
+                .unwrap_or_else(|e| format!("error: {e}")),
+            None => "error: the config didn't come from a file".to_string(),
+        },
+        None if command == "status" => {
+            let status = status::snapshot().await;
+            status
+                .paths
+                .iter()
+                .map(|(name, path_status)| {
+                    format!(
+                        "{name}: {}, {} moved, {} errors",
+                        path_status.state_label(),
+                        path_status.moved,
+                        path_status.errors
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        _ => {
+            format!("error: unknown command {command:?}, expected status, reload or resume <name>")
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use crate::config::{LinkMode, PathConfig};
+use crate::status;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+// With avoid_cache, how much is copied between flushing and dropping it from the cache
+const CACHE_DROP_INTERVAL: u64 = 64 * 1024 * 1024;
+
+// How a path's files are copied, taken from its config
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CopyOptions {
+    pub link_mode: LinkMode,
+    // Read/write buffer for chunked copies, None for the default
+    pub buffer_bytes: Option<usize>,
+    // Drop copied data from the page cache, where the platform supports it
+    pub avoid_cache: bool,
+}
+
+pub async fn copy_file(
+    source: PathBuf,
+    destination: PathBuf,
+    progress: Option<Arc<Progress>>,
+    options: CopyOptions,
+) -> std::io::Result<u64> {
+    tokio::task::spawn_blocking(move || {
+        copy_blocking(&source, &destination, progress.as_deref(), options)
+    })
+    .await?
+}
+
+// Copies without progress or a configured buffer use std::fs::copy, which can hand the work to
+// the kernel
+pub(crate) fn copy_blocking(
+    source: &Path,
+    destination: &Path,
+    progress: Option<&Progress>,
+    options: CopyOptions,
+) -> std::io::Result<u64> {
+    // Opening the destination for writing would truncate the source
+    if is_same_file(source, destination) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} and {} are the same file",
+                source.display(),
+                destination.display()
+            ),
+        ));
+    }
+
+    if matches!(options.link_mode, LinkMode::Reflink) {
+        match reflink_copy::reflink(source, destination) {
+            Ok(_) => {
+                let size = destination.metadata()?.len();
+                if let Some(progress) = progress {
+                    progress.advance(size);
+                }
+                return Ok(size);
+            }
+            // Unsupported filesystem, different devices or an existing destination
+            Err(e) => debug!("Reflink of {} failed, copying: {}", source.display(), e),
+        }
+    }
+
+    // Elsewhere there is no way to advise the cache, so the copy is the normal one
+    let avoid_cache = options.avoid_cache && cfg!(target_os = "linux");
+    if progress.is_none() && options.buffer_bytes.is_none() && !avoid_cache {
+        return std::fs::copy(source, destination);
+    }
+    copy_chunked(
+        source,
+        destination,
+        progress,
+        options.buffer_bytes.unwrap_or(DEFAULT_BUFFER_SIZE),
+        avoid_cache,
+    )
+}
+
+// Through symlinks, and on Unix hardlinks as well
+fn is_same_file(source: &Path, destination: &Path) -> bool {
+    if let (Ok(source), Ok(destination)) = (source.canonicalize(), destination.canonicalize())
+        && source == destination
+    {
+        return true;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let (Ok(source), Ok(destination)) = (source.metadata(), destination.metadata()) {
+            return source.dev() == destination.dev() && source.ino() == destination.ino();
+        }
+    }
+    false
+}
+
+fn copy_chunked(
+    source: &Path,
+    destination: &Path,
+    progress: Option<&Progress>,
+    buffer_size: usize,
+    avoid_cache: bool,
+) -> std::io::Result<u64> {
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(destination)?;
+    let mut buffer = vec![0; buffer_size];
+    let mut copied = 0;
+    let mut dropped = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if let Some(progress) = progress {
+            progress.advance(read as u64);
+        }
+        if avoid_cache && copied - dropped >= CACHE_DROP_INTERVAL {
+            drop_from_cache(&reader, &writer)?;
+            dropped = copied;
+        }
+    }
+    if avoid_cache {
+        drop_from_cache(&reader, &writer)?;
+    }
+
+    // Same as std::fs::copy
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(copied)
+}
+
+// Written pages are only dropped once they are on disk, so the destination is flushed first
+#[cfg(target_os = "linux")]
+fn drop_from_cache(source: &File, destination: &File) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    destination.sync_data()?;
+    for file in [source, destination] {
+        // Only advice: a kernel that ignores it keeps caching, which is no reason to fail the copy
+        // SAFETY: the descriptor belongs to an open File for the duration of the call
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_from_cache(_source: &File, _destination: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Logs how far a copy has got, every `progress_secs` and/or `progress_percent`. A directory copy
+// shares one across its files so the numbers cover the whole folder
+pub struct Progress {
+    // The path's, for publishing to its status
+    name: String,
+    label: PathBuf,
+    total: u64,
+    every: Option<Duration>,
+    every_percent: Option<u64>,
+    state: std::sync::Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    copied: u64,
+    logged_at: Instant,
+    logged_percent: u64,
+}
+
+impl Progress {
+    // None when the path doesn't log progress
+    pub fn new(config: &PathConfig, path: &Path) -> Option<Arc<Self>> {
+        if config.progress_secs.is_none() && config.progress_percent.is_none() {
+            return None;
+        }
+
+        let total = if path.is_dir() {
+            WalkDir::new(path)
+                .into_iter()
+                .filter_entry(|entry| {
+                    entry.depth() == 0 || !config.is_ignored_name(entry.file_name())
+                })
+                .flatten()
+                .filter(|entry| entry.file_type().is_file())
+                .filter_map(|entry| entry.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum()
+        } else {
+            path.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+        };
+
+        Some(Arc::new(Self {
+            name: config.name.clone(),
+            label: path.to_path_buf(),
+            total,
+            every: config.progress_secs.map(Duration::from_secs),
+            every_percent: config.progress_percent,
+            state: std::sync::Mutex::new(ProgressState {
+                copied: 0,
+                logged_at: Instant::now(),
+                logged_percent: 0,
+            }),
+        }))
+    }
+
+    pub(crate) fn advance(&self, bytes: u64) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+
+        state.copied += bytes;
+        status::record_progress(&self.name, &self.label, state.copied, self.total);
+        let percent = (state.copied * 100).checked_div(self.total).unwrap_or(100);
+        let time_due = self
+            .every
+            .is_some_and(|every| state.logged_at.elapsed() >= every);
+        let percent_due = self
+            .every_percent
+            .is_some_and(|every| percent >= state.logged_percent + every);
+
+        if time_due || percent_due {
+            info!(
+                "Copying {}: {}/{} bytes ({percent}%)",
+                self.label.display(),
+                state.copied,
+                self.total
+            );
+            state.logged_at = Instant::now();
+            state.logged_percent = percent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `destination` makes the second name for `file` inside the test folder
+    fn copy_onto_itself(name: &str, destination: impl FnOnce(&Path) -> PathBuf) {
+        let root = std::env::temp_dir().join(format!(
+            "anime-organizer-copy-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("Show - 01.mkv");
+        std::fs::write(&file, "episode").unwrap();
+        let destination = destination(&file);
+
+        let chunked = CopyOptions {
+            buffer_bytes: Some(4),
+            ..CopyOptions::default()
+        };
+        for options in [CopyOptions::default(), chunked] {
+            let error = copy_blocking(&file, &destination, None, options).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert_eq!(std::fs::read(&file).unwrap(), b"episode");
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn copying_a_file_onto_itself_is_refused() {
+        copy_onto_itself("same", Path::to_path_buf);
+    }
+
+    // Only Unix tells hardlinks apart by inode
+    #[cfg(unix)]
+    #[test]
+    fn copying_onto_a_hardlink_of_the_source_is_refused() {
+        copy_onto_itself("hardlink", |file| {
+            let link = file.with_file_name("Hardlink - 01.mkv");
+            std::fs::hard_link(file, &link).unwrap();
+            link
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copying_onto_a_symlink_to_the_source_is_refused() {
+        copy_onto_itself("symlink", |file| {
+            let link = file.with_file_name("Symlink - 01.mkv");
+            std::os::unix::fs::symlink(file, &link).unwrap();
+            link
+        });
+    }
+}
@@ -1,203 +1,2146 @@
-use crate::{DETECTED_FILES, MOVED_FILES, PathConfig, copy_file};
-use anitomy::ElementKind;
-use notify::{RecursiveMode, Watcher};
+use crate::config::{
+    AgeSource, ConflictPolicy, CopyOrder, DedupPolicy, NestedEvents, PathConfig, WatchedEvent,
+    WatcherTypeConfig,
+};
+use crate::copy::Progress;
+use crate::error::OrganizerError;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::metadata::{self, Metadata};
+use crate::status::{self, PathStatus, WatcherState};
+use crate::{DETECTED_FILES, MOVED_FILES, RESERVED_DESTINATIONS, active_moves, state, template};
+use crate::{
+    dedup, deletion, mount, path_limit, permissions, provenance, prune, resolver, rules, transcode,
+    unparsed,
+    watch::{self, Watched},
+};
+use notify::event::{AccessKind, AccessMode};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use tracing::{error, info};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{Instrument, error, info, warn};
 
-pub struct CopyWatcher(PathConfig);
+// How long a quiet watcher waits for more of a batch before ordering it by copy_order
+const COPY_ORDER_WINDOW: Duration = Duration::from_secs(1);
+
+// Last time a detected file was written to
+type Activity = std::sync::Mutex<Instant>;
+
+pub struct CopyWatcher {
+    config: Arc<PathConfig>,
+    fs: Arc<dyn FileSystem>,
+    // Movers waiting on a close-write event for their file, keyed by the detected path
+    ready_signals: Mutex<HashMap<PathBuf, Weak<Notify>>>,
+    // With settle_secs, when each detected file (or anything inside a detected folder) last changed
+    activity: Mutex<HashMap<PathBuf, Weak<Activity>>>,
+    transfers: Arc<Semaphore>,
+}
 
 impl CopyWatcher {
     pub fn new(config: PathConfig) -> Self {
-        Self(config)
+        Self {
+            transfers: Arc::new(Semaphore::new(config.max_concurrent())),
+            config: Arc::new(config),
+            fs: Arc::new(RealFileSystem),
+            ready_signals: Mutex::new(HashMap::new()),
+            activity: Mutex::new(HashMap::new()),
+        }
     }
 
-    pub async fn start(&self) -> Result<(), anyhow::Error> {
-        info!("Starting {} thread", self.0.name);
+    pub(crate) fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
+    }
 
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-        let mut watcher =
-            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
-                let event = event.unwrap();
-                if let notify::EventKind::Create(_) = event.kind {
-                    tx.send(event).unwrap();
-                }
-            })?;
+    #[tracing::instrument(skip_all, fields(name = %self.config.name))]
+    pub async fn start(&self) -> Result<(), OrganizerError> {
+        info!("Starting {} thread", self.config.name);
 
-        let path = self.0.source.clone();
-        watcher.watch(Path::new(&path), RecursiveMode::Recursive)?;
-        while let Some(event) = rx.recv().await {
+        let close_write_events = self.config.close_write_events;
+        let events = self.config.watched_events();
+        let settle = self.config.settle().is_some();
+        let listed = self.config.clone();
+        let (mut watcher, mut rx) = watch::event_channel(
+            self.config.channel_capacity(),
+            self.config.poll_fallback(),
+            move |event| {
+                event.paths.iter().any(|path| listed.is_listed(path))
+                    && match event.kind {
+                        notify::EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                            close_write_events
+                        }
+                        // Writes to a file being settled keep it waiting, whether or not Modify
+                        // is watched
+                        _ => watch::classify(&event.kind).is_some_and(|kind| {
+                            events.contains(&kind) || (settle && kind == WatchedEvent::Modify)
+                        }),
+                    }
+            },
+        )
+        .map_err(OrganizerError::watch(&self.config.source))?;
+
+        let path = self.config.source.clone();
+        let mode = self.config.watch_mode();
+        let mut resumes = status::subscribe_resumes();
+        watcher
+            .watch(Path::new(&path), mode)
+            .map_err(OrganizerError::watch(&path))?;
+        // Watching first, so nothing created during the scan slips through. Files seen by both
+        // are only organized once
+        if self.config.process_existing {
+            mount::wait_until_available(&self.config).await;
+            status::update(&self.config.name, |status| {
+                status.state = WatcherState::Syncing
+            })
+            .await;
+            self.process_existing()
+                .await
+                .map_err(OrganizerError::copy(&self.config.source))?;
+        }
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Watching
+        })
+        .await;
+
+        loop {
+            let watched = tokio::select! {
+                watched = watch::next_event(&mut rx, &mut watcher, &path, mode) => watched,
+                paused_since = status::resumed(&mut resumes, &self.config.name) => {
+                    self.catch_up(paused_since, "Resumed").await;
+                    continue;
+                }
+            };
+            let event = match watched {
+                Some(Watched::Event(event)) => event,
+                Some(Watched::Dropped(since)) => {
+                    self.catch_up(since, "Events were dropped").await;
+                    continue;
+                }
+                None => break,
+            };
             match event.kind {
-                notify::EventKind::Create(_) => self.copy_file(event.paths).await,
-                _ => unreachable!(),
+                notify::EventKind::Access(_) => self.mark_ready(event.paths).await,
+                // The old name of a rename is gone, so only paths that still exist are organized
+                _ if self.only_activity(&event).await => {}
+                _ => {
+                    status::record_event(&self.config.name).await;
+                    let mut paths: Vec<PathBuf> = event
+                        .paths
+                        .into_iter()
+                        .filter(|path| self.fs.exists(path) && self.config.is_listed(path))
+                        .collect();
+                    // Collect the rest of the batch so it can be ordered as a whole
+                    let mut dropped = None;
+                    if self.config.copy_order != CopyOrder::AsDetected {
+                        while let Ok(Some(watched)) = tokio::time::timeout(
+                            COPY_ORDER_WINDOW,
+                            watch::next_event(&mut rx, &mut watcher, &path, mode),
+                        )
+                        .await
+                        {
+                            let event = match watched {
+                                Watched::Event(event) => event,
+                                Watched::Dropped(since) => {
+                                    dropped = Some(since);
+                                    break;
+                                }
+                            };
+                            match event.kind {
+                                notify::EventKind::Access(_) => self.mark_ready(event.paths).await,
+                                _ if self.only_activity(&event).await => {}
+                                _ => {
+                                    status::record_event(&self.config.name).await;
+                                    paths.extend(event.paths.into_iter().filter(|path| {
+                                        self.fs.exists(path) && self.config.is_listed(path)
+                                    }))
+                                }
+                            }
+                        }
+                    }
+                    self.copy_file(paths).await;
+                    if let Some(since) = dropped {
+                        self.catch_up(since, "Events were dropped").await;
+                    }
+                }
             }
         }
 
         Ok(())
     }
 
-    async fn copy_file(&self, paths: Vec<PathBuf>) {
+    fn sort_by_copy_order(&self, paths: &mut [PathBuf]) {
+        match self.config.copy_order {
+            CopyOrder::AsDetected => {}
+            CopyOrder::SmallestFirst => paths.sort_by_cached_key(|path| {
+                self.fs
+                    .metadata(path)
+                    .map(|metadata| metadata.len)
+                    .unwrap_or(u64::MAX)
+            }),
+            CopyOrder::Alphabetical => {
+                paths.sort_by_cached_key(|path| path.file_name().map(ToOwned::to_owned))
+            }
+        }
+    }
+
+    // Listed entries of the source modified at or after `watermark` (seconds since the epoch), all
+    // of them without one
+    fn modified_since(&self, watermark: Option<u64>) -> std::io::Result<Vec<PathBuf>> {
+        Ok(self
+            .fs
+            .read_dir(&self.config.source)?
+            .into_iter()
+            .filter(|path| self.config.is_listed(path))
+            .filter(|path| {
+                let Some(watermark) = watermark else {
+                    return true;
+                };
+                self.fs
+                    .metadata(path)
+                    .ok()
+                    .and_then(|metadata| metadata.modified)
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .is_none_or(|modified| modified.as_secs() >= watermark)
+            })
+            .collect())
+    }
+
+    // Events were dropped, while the path was paused or because the channel stayed full, so what
+    // changed since is organized instead
+    async fn catch_up(&self, since: SystemTime, reason: &str) {
+        let watermark = since
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self.modified_since(Some(watermark)) {
+            Ok(changed) => {
+                info!(
+                    "{reason}, organizing {} entries changed since",
+                    changed.len()
+                );
+                self.copy_file(changed).await;
+            }
+            Err(e) => error!(
+                "Could not scan {} to catch up: {}",
+                self.config.source.display(),
+                e
+            ),
+        }
+    }
+
+    // Only entries modified since the previous scan, so restarts don't walk a large backlog again
+    async fn process_existing(&self) -> std::io::Result<()> {
+        let scanned_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let watermark = state::scan_watermark(&self.config.name).await;
+        let existing = self.modified_since(watermark)?;
+
+        info!(
+            "Organizing {} existing entries{}",
+            existing.len(),
+            if watermark.is_some() {
+                " modified since the last scan"
+            } else {
+                ""
+            }
+        );
+        self.copy_file(existing).await;
+
+        let name = self.config.name.clone();
+        state::update(|state| {
+            state.scan_watermarks.insert(name, scanned_at);
+        })
+        .await;
+        Ok(())
+    }
+
+    async fn copy_file(&self, mut paths: Vec<PathBuf>) {
+        if status::is_paused(&self.config.name).await || !mount::source_available(&self.config) {
+            return;
+        }
+        // Movers are queued on max_concurrent in the order they are spawned
+        self.sort_by_copy_order(&mut paths);
+
         let mut detected_files = DETECTED_FILES.lock().await;
+        let batch: HashSet<PathBuf> = paths.iter().cloned().collect();
         for path in paths {
             if path.ends_with(".partial") || detected_files.contains(&path) {
                 continue;
             }
+            if self.config.nested_events == NestedEvents::Parent
+                && let Some(parent) = self.detected_parent(&path, &batch, &detected_files)
+            {
+                info!(
+                    "Skipping {}, it is organized along with {}",
+                    path.display(),
+                    parent.display()
+                );
+                continue;
+            }
+
+            if self.config.is_ignored(&path) {
+                info!("Ignoring {}", path.display());
+                continue;
+            }
 
             detected_files.insert(path.clone());
-            self.spawn_mover(path);
+            let ready = self.register_ready_signal(&path).await;
+            let activity = self.register_activity(&path).await;
+            self.spawn_mover(path, ready, activity);
+        }
+    }
+
+    // The folder above the path that is in the same batch or already has a mover, if any
+    fn detected_parent<'a>(
+        &self,
+        path: &'a Path,
+        batch: &HashSet<PathBuf>,
+        detected_files: &HashSet<PathBuf>,
+    ) -> Option<&'a Path> {
+        path.ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != self.config.source)
+            .filter(|ancestor| ancestor.starts_with(&self.config.source))
+            .find(|ancestor| batch.contains(*ancestor) || detected_files.contains(*ancestor))
+    }
+
+    async fn register_activity(&self, path: &Path) -> Option<Arc<Activity>> {
+        self.config.settle()?;
+
+        let activity = Arc::new(Activity::new(Instant::now()));
+        let mut tracked = self.activity.lock().await;
+        tracked.retain(|_, activity| activity.strong_count() > 0);
+        tracked.insert(path.to_path_buf(), Arc::downgrade(&activity));
+        Some(activity)
+    }
+
+    // Records a Modify as activity on the detected file it touches, or the detected folder it is
+    // in. True when the event was only wanted for that and is not organized itself
+    async fn only_activity(&self, event: &notify::Event) -> bool {
+        if self.config.settle().is_none()
+            || watch::classify(&event.kind) != Some(WatchedEvent::Modify)
+        {
+            return false;
+        }
+
+        let tracked = self.activity.lock().await;
+        for path in &event.paths {
+            for activity in path
+                .ancestors()
+                .take_while(|ancestor| ancestor.starts_with(&self.config.source))
+                .filter_map(|ancestor| tracked.get(ancestor).and_then(Weak::upgrade))
+            {
+                *activity
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Instant::now();
+            }
+        }
+        !self.config.watched_events().contains(&WatchedEvent::Modify)
+    }
+
+    async fn register_ready_signal(&self, path: &Path) -> Option<Arc<Notify>> {
+        if !self.config.close_write_events {
+            return None;
+        }
+
+        let ready = Arc::new(Notify::new());
+        let mut ready_signals = self.ready_signals.lock().await;
+        ready_signals.retain(|_, signal| signal.strong_count() > 0);
+        ready_signals.insert(path.to_path_buf(), Arc::downgrade(&ready));
+        Some(ready)
+    }
+
+    // A close-write means the writer is done with the file, so its mover can skip the wait
+    async fn mark_ready(&self, paths: Vec<PathBuf>) {
+        let ready_signals = self.ready_signals.lock().await;
+        for path in paths {
+            if let Some(ready) = ready_signals.get(&path).and_then(Weak::upgrade) {
+                ready.notify_one();
+            }
         }
     }
 
-    fn spawn_mover(&self, path: PathBuf) {
+    fn spawn_mover(
+        &self,
+        path: PathBuf,
+        ready: Option<Arc<Notify>>,
+        activity: Option<Arc<Activity>>,
+    ) {
         info!("{} found, moving to correct folder", path.display());
-        let mover = Mover::new(self.0.destination.clone(), path, self.0.place_in_sub);
-        tokio::spawn(async move {
-            mover.start().await;
-        });
+        let (config, fs, transfers) = (
+            rules::current(&self.config),
+            self.fs.clone(),
+            self.transfers.clone(),
+        );
+        tokio::spawn(
+            async move {
+                let Some(mut mover) =
+                    Mover::new_in_background(config, fs, path, ready, transfers).await
+                else {
+                    return;
+                };
+                mover.activity = activity;
+                mover.start().await;
+            }
+            .in_current_span(),
+        );
     }
 }
 
 impl From<PathConfig> for CopyWatcher {
     fn from(path: PathConfig) -> CopyWatcher {
-        Self(path)
+        Self::new(path)
     }
 }
 
-struct Mover {
-    destination: PathBuf,
+pub(crate) struct Mover {
+    config: Arc<PathConfig>,
+    fs: Arc<dyn FileSystem>,
     detected_file: PathBuf,
-    subfolder: bool,
     wait_time: Duration,
-    title: Option<String>,
+    metadata: Metadata,
+    ready: Option<Arc<Notify>>,
+    // Shared by all movers of a path to enforce max_concurrent
+    transfers: Arc<Semaphore>,
+    // The video this file is a sidecar (subtitle, thumbnail, ...) of
+    sidecar_of: Option<PathBuf>,
+    // With settle_secs, when the file was last written to
+    activity: Option<Arc<Activity>>,
 }
 
 impl Mover {
-    fn new(destination: PathBuf, detected_file: PathBuf, subfolder: bool) -> Self {
-        let title = get_title(&detected_file);
-        let wait_time = Duration::from_secs(5);
+    pub(crate) fn new(
+        config: Arc<PathConfig>,
+        fs: Arc<dyn FileSystem>,
+        detected_file: PathBuf,
+        ready: Option<Arc<Notify>>,
+        transfers: Arc<Semaphore>,
+    ) -> Self {
+        let sidecar_of = find_sidecar_video(&*fs, &config, &detected_file);
+        // Sidecars are organized by the video's name so both land in the same place
+        let metadata = match &sidecar_of {
+            Some(video) => {
+                info!(
+                    "{} is a sidecar of {}",
+                    detected_file.display(),
+                    video.display()
+                );
+                Metadata::parse(video, &config.title_rules, config.parser, config.anitomy)
+            }
+            None => Metadata::parse(
+                &detected_file,
+                &config.title_rules,
+                config.parser,
+                config.anitomy,
+            ),
+        };
+        let wait_time = config.wait_time();
         Self {
-            destination,
+            config,
+            fs,
             detected_file,
-            subfolder,
             wait_time,
-            title,
+            metadata,
+            ready,
+            transfers,
+            sidecar_of,
+            activity: None,
         }
     }
 
-    async fn start(&self) {
-        let destination = self.setup_destination_folder();
-        self.perform_move(destination).await;
+    // Mover::new off the runtime's threads: parsing the name is CPU work, and finding the video of
+    // a sidecar reads its folder, which adds up over a large batch. None when parsing panicked,
+    // the file is then forgotten so a later event can try again
+    pub(crate) async fn new_in_background(
+        config: Arc<PathConfig>,
+        fs: Arc<dyn FileSystem>,
+        detected_file: PathBuf,
+        ready: Option<Arc<Notify>>,
+        transfers: Arc<Semaphore>,
+    ) -> Option<Self> {
+        let span = tracing::Span::current();
+        let file = detected_file.clone();
+        let mover = tokio::task::spawn_blocking(move || {
+            span.in_scope(|| Self::new(config, fs, file, ready, transfers))
+        })
+        .await;
+        match mover {
+            Ok(mover) => Some(mover),
+            Err(e) => {
+                error!("Could not parse {}: {}", detected_file.display(), e);
+                DETECTED_FILES.lock().await.remove(&detected_file);
+                None
+            }
+        }
     }
 
-    fn setup_destination_folder(&self) -> PathBuf {
-        let mut destination = PathBuf::new();
+    // Returns where the file ended up, None when it was skipped or given up on
+    pub(crate) async fn start(&self) -> Option<PathBuf> {
+        let _active = ActiveMove::new(&self.detected_file);
+        if !self.config.allows_group(self.metadata.group.as_deref()) {
+            info!(
+                "Skipping {}, release group {} is not allowed",
+                self.detected_file.display(),
+                self.metadata.group.as_deref().unwrap_or("(none)")
+            );
+            DETECTED_FILES.lock().await.remove(&self.detected_file);
+            return None;
+        }
 
-        if let Some(title) = &self.title
-            && self.subfolder
+        if self.config.prefer_latest_version
+            && let Some(newer) = self.newer_version()
         {
-            let mut folder = PathBuf::from(&self.destination);
-            folder.push(title);
-            create_folder(&folder);
-            destination.push(&folder);
-            destination.push(self.detected_file.file_name().unwrap());
-        } else {
-            create_folder(&self.destination);
-            destination.push(&self.destination);
-            destination.push(self.detected_file.file_name().unwrap());
+            info!(
+                "Skipping {}, {} is a newer version",
+                self.detected_file.display(),
+                newer.display()
+            );
+            DETECTED_FILES.lock().await.remove(&self.detected_file);
+            return None;
         }
 
+        if self.config.flatten_single_file_dirs
+            && self.fs.is_dir(&self.detected_file)
+            && let Some(video_destination) = self.flatten().await
+        {
+            return video_destination;
+        }
+
+        self.record(|status| status.queued += 1).await;
+        let destination = self.setup_destination_folder().await;
+        let Some(destination) = self.resolve_conflict(destination).await else {
+            self.record(|status| status.queued -= 1).await;
+            DETECTED_FILES.lock().await.remove(&self.detected_file);
+            return None;
+        };
+
+        let result = match self.config.watcher_type {
+            WatcherTypeConfig::Sort => self.perform_sort(destination.clone()).await,
+            _ => self.perform_move(destination.clone()).await,
+        };
+        RESERVED_DESTINATIONS.lock().await.remove(&destination);
+        result
+    }
+
+    // A video next to this file's video (or this video) with the same episode and a higher
+    // version, e.g. `v2` of a batch that also contains the original
+    fn newer_version(&self) -> Option<PathBuf> {
+        let video = self.sidecar_of.as_ref().unwrap_or(&self.detected_file);
+        let version = self.metadata.version_number();
+        self.fs
+            .read_dir(video.parent()?)
+            .ok()?
+            .into_iter()
+            .filter(|path| path != video && metadata::is_video(path))
+            .find(|path| {
+                let other = Metadata::parse(
+                    path,
+                    &self.config.title_rules,
+                    self.config.parser,
+                    self.config.anitomy,
+                );
+                other.same_episode(&self.metadata) && other.version_number() > version
+            })
+    }
+
+    // Sidecars are routed by their video's name so both land in the same library
+    fn destination_root(&self) -> PathBuf {
+        let file = self.sidecar_of.as_ref().unwrap_or(&self.detected_file);
+        let mut root = self.config.destination_for(file);
+        if root == self.config.destination
+            && let Some(by_age) = self.age_destination(file)
+        {
+            root = by_age;
+        }
+        let root = match self.language_folder() {
+            Some(folder) => root.join(folder),
+            None => root.to_path_buf(),
+        };
+        match self.subtitle_folder() {
+            Some(folder) => root.join(folder),
+            None => root,
+        }
+    }
+
+    // destination_hot or destination_cold by how old the file is, None when it can't be told
+    fn age_destination(&self, file: &Path) -> Option<&Path> {
+        let max_age = self.config.hot_max_age()?;
+        let hot = self.config.destination_hot.as_deref()?;
+        let cold = self.config.destination_cold.as_deref()?;
+
+        let modified = || self.fs.metadata(file).ok()?.modified;
+        let released = || {
+            let year = self.metadata.year.as_deref()?.parse().ok()?;
+            end_of_year(year)
+        };
+        let date = match self.config.age_from {
+            AgeSource::Modified => modified().or_else(released),
+            AgeSource::Year => released().or_else(modified),
+        }?;
+        let age = SystemTime::now().duration_since(date).unwrap_or_default();
+        Some(if age <= max_age { hot } else { cold })
+    }
+
+    fn subtitle_folder(&self) -> Option<&Path> {
+        self.metadata
+            .subtitles
+            .iter()
+            .find_map(|term| {
+                self.config
+                    .subtitle_folders
+                    .iter()
+                    .find(|(listed, _)| listed.eq_ignore_ascii_case(term))
+                    .map(|(_, folder)| folder.as_path())
+            })
+            .or(self.config.default_subtitle_folder.as_deref())
+    }
+
+    fn language_folder(&self) -> Option<&Path> {
+        self.metadata.languages.iter().find_map(|language| {
+            self.config
+                .language_folders
+                .iter()
+                .find(|(term, _)| term.eq_ignore_ascii_case(language))
+                .map(|(_, folder)| folder.as_path())
+        })
+    }
+
+    async fn setup_destination_folder(&self) -> PathBuf {
+        let resolved = self.resolve_folder().await;
+        if resolved.is_none()
+            && self.source_folder().is_none()
+            && self.config.place_in_sub
+            && (self.metadata.title.is_none()
+                || !self.metadata.has_all(&self.config.required_elements))
+        {
+            unparsed::record(self.detected_file.clone()).await;
+        }
+
+        let mut destination = self.compute_destination(resolved.as_deref());
+        if resolved.is_none() && self.source_folder().is_none() && self.config.place_in_sub {
+            destination = self.match_existing_title(destination);
+        }
+        if let Some(folder) = destination.parent() {
+            create_folder(
+                &*self.fs,
+                &self.destination_root(),
+                folder,
+                self.config.dir_mode,
+            );
+        }
+        destination
+    }
+
+    // Where the file goes, from the parsed metadata and the path's layout options alone: nothing
+    // is created or recorded. `resolved_folder` is the destination resolver's answer, if any
+    pub(crate) fn compute_destination(&self, resolved_folder: Option<&Path>) -> PathBuf {
+        let root = self.destination_root();
+        let mut folder = root.clone();
+        if let Some(resolved) = resolved_folder {
+            folder.push(resolved);
+        } else if let Some(relative) = self.source_folder() {
+            folder.push(relative);
+        } else if self.config.place_in_sub
+            // A dubious parse would only create a junk folder, so the file waits in the root
+            && self.metadata.has_all(&self.config.required_elements)
+        {
+            folder.push(self.subfolder());
+            if let Some(extras_folder) = self.extras_folder() {
+                folder.push(extras_folder);
+            } else if let Some(batch_folder) = &self.config.batch_folder
+                && self.metadata.is_batch()
+            {
+                folder.push(batch_folder);
+            }
+        }
+
+        let mut destination = folder.join(self.destination_file_name());
+        if cfg!(windows) || self.config.trim_trailing_dots {
+            destination = path_limit::trim_trailing_dots(&root, destination);
+        }
+        if self.config.truncate_long_paths {
+            destination = path_limit::fit(&root, destination);
+        }
         destination
     }
 
-    async fn perform_move(&self, destination: PathBuf) {
+    // With fuzzy_title_threshold, a title folder that doesn't exist yet is replaced by the most
+    // similar existing folder next to it. The title folder is found by where the subfolder layout
+    // puts the title, and compared as it was rendered (decorated, trimmed or shortened)
+    fn match_existing_title(&self, destination: PathBuf) -> PathBuf {
+        let (Some(threshold), Some(title)) =
+            (self.config.fuzzy_title_threshold, &self.metadata.title)
+        else {
+            return destination;
+        };
+        let title_folder = match &self.config.folder_template {
+            Some(folder_template) => template::folder_of(folder_template, &self.metadata, "title"),
+            None => (Path::new(title).components().count() == 1).then_some(0),
+        };
+        let root = self.destination_root();
+        let Ok(relative) = destination.strip_prefix(&root) else {
+            return destination;
+        };
+        let components: Vec<_> = relative.components().collect();
+        // Without any folder after the root the file waits there, unsorted
+        let Some(index) = title_folder.filter(|index| index + 1 < components.len()) else {
+            return destination;
+        };
+
+        let mut parent = root.clone();
+        parent.extend(&components[..index]);
+        let title_folder = components[index].as_os_str();
+        if self.fs.exists(&parent.join(title_folder)) {
+            return destination;
+        }
+        let title = title_folder.to_string_lossy().to_lowercase();
+        let Some((similarity, existing)) = self
+            .fs
+            .read_dir(&parent)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| self.fs.is_dir(entry))
+            .filter_map(|entry| {
+                let name = entry.file_name()?.to_string_lossy().to_lowercase();
+                Some((strsim::normalized_levenshtein(&title, &name), entry))
+            })
+            .filter(|(similarity, _)| *similarity >= threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        else {
+            return destination;
+        };
+
+        info!(
+            "Using {} for {} ({:.0}% similar)",
+            existing.display(),
+            self.detected_file.display(),
+            similarity * 100.0
+        );
+        let mut destination = existing;
+        destination.extend(&components[index + 1..]);
+        destination
+    }
+
+    // Organizes the only video of a wrapper folder (and its sidecars) without the folder.
+    // Returns None when the folder holds anything other than a single video, otherwise what
+    // organizing the video returned
+    async fn flatten(&self) -> Option<Option<PathBuf>> {
+        loop {
+            self.wait_until_ready().await;
+            if !is_downloading(&*self.fs, &self.detected_file) {
+                break;
+            }
+        }
+
+        let Ok(entries) = self.fs.read_dir(&self.detected_file) else {
+            return None;
+        };
+        let files: Vec<PathBuf> = entries
+            .into_iter()
+            .filter(|path| self.fs.is_file(path))
+            .collect();
+        let mut videos = files.iter().filter(|path| metadata::is_video(path));
+        let (Some(video), None) = (videos.next(), videos.next()) else {
+            return None;
+        };
+
+        info!(
+            "{} only contains {}, organizing it without the folder",
+            self.detected_file.display(),
+            video.display()
+        );
+        let mut video_destination = None;
+        for file in files.iter().filter(|file| {
+            *file == video
+                || find_sidecar_video(&*self.fs, &self.config, file).as_ref() == Some(video)
+        }) {
+            let mover = Mover::new(
+                self.config.clone(),
+                self.fs.clone(),
+                file.clone(),
+                None,
+                self.transfers.clone(),
+            );
+            let destination = Box::pin(mover.start()).await;
+            if file == video {
+                video_destination = destination;
+            }
+        }
+
+        Some(video_destination)
+    }
+
+    // None means the existing destination should be kept and nothing copied
+    // Conflicts are decided by the final destination, whichever source or path the other file
+    // came from. The chosen destination stays reserved until the move is done
+    async fn resolve_conflict(&self, destination: PathBuf) -> Option<PathBuf> {
+        let mut reserved = RESERVED_DESTINATIONS.lock().await;
+        let in_flight = reserved.contains(&destination);
+        if (!in_flight && !self.fs.exists(&destination)) || destination == self.detected_file {
+            reserved.insert(destination.clone());
+            return Some(destination);
+        }
+
+        let destination = match self.config.on_conflict() {
+            // Writing over a file another move is still writing would corrupt both
+            ConflictPolicy::Overwrite if !in_flight => destination,
+            ConflictPolicy::Skip => {
+                info!(
+                    "{} already exists, skipping {}",
+                    destination.display(),
+                    self.detected_file.display()
+                );
+                return None;
+            }
+            ConflictPolicy::Overwrite | ConflictPolicy::Rename => {
+                let renamed = free_path(&*self.fs, &destination, &reserved);
+                info!(
+                    "{} already exists, using {}",
+                    destination.display(),
+                    renamed.display()
+                );
+                renamed
+            }
+        };
+        reserved.insert(destination.clone());
+        Some(destination)
+    }
+
+    // With preserve_structure, the folder the file is in below the source
+    fn source_folder(&self) -> Option<&Path> {
+        if !self.config.preserve_structure {
+            return None;
+        }
+        self.detected_file
+            .strip_prefix(&self.config.source)
+            .ok()?
+            .parent()
+    }
+
+    async fn resolve_folder(&self) -> Option<PathBuf> {
+        let command = self.config.destination_resolver_command.as_ref()?;
+        let file = self.sidecar_of.as_ref().unwrap_or(&self.detected_file);
+        resolver::resolve(
+            command,
+            self.config.resolver_timeout(),
+            self.config.anitomy,
+            file,
+        )
+        .await
+    }
+
+    fn subfolder(&self) -> PathBuf {
+        match &self.config.folder_template {
+            Some(folder_template) => template::render_path(folder_template, &self.metadata),
+            None => self.metadata.title.iter().collect(),
+        }
+    }
+
+    fn extras_folder(&self) -> Option<&str> {
+        self.metadata.types.iter().find_map(|kind| {
+            self.config
+                .extras_folders
+                .iter()
+                .find(|(keyword, _)| keyword.eq_ignore_ascii_case(kind))
+                .map(|(_, folder)| folder.as_str())
+        })
+    }
+
+    fn destination_file_name(&self) -> OsString {
+        let file_name = self.detected_file.file_name().unwrap().to_os_string();
+        let Some(rename_template) = &self.config.rename_template else {
+            return file_name;
+        };
+
+        if self.fs.is_dir(&self.detected_file) {
+            return file_name;
+        }
+
+        let Some(stem) = template::render(rename_template, &self.metadata) else {
+            return file_name;
+        };
+
+        match (&self.sidecar_of, self.detected_file.extension()) {
+            // Keep everything after the video's stem, e.g. `.en.srt`
+            (Some(video), _) => {
+                let video_stem = video.file_stem().unwrap_or_default().to_string_lossy();
+                let file_name = file_name.to_string_lossy();
+                format!("{stem}{}", &file_name[video_stem.len()..]).into()
+            }
+            (None, Some(extension)) => format!("{stem}.{}", extension.to_string_lossy()).into(),
+            (None, None) => stem.into(),
+        }
+    }
+
+    async fn perform_move(&self, destination: PathBuf) -> Option<PathBuf> {
         let mut file_moved = false;
+        let mut started = false;
+        let mut failures = 0;
+        let mut attempts = 0;
         while !file_moved {
-            tokio::time::sleep(self.wait_time).await;
+            self.wait_until_ready().await;
+
+            // The download was cancelled or the file moved away before it could be organized
+            if !self.fs.exists(&self.detected_file) {
+                info!(
+                    "{} no longer exists, not moving it",
+                    self.detected_file.display()
+                );
+                self.abandon(started).await;
+                return None;
+            }
 
-            if is_downloading(&self.detected_file) {
+            // Retrying against a broken destination would only add to the errors
+            if status::is_paused(&self.config.name).await {
+                info!(
+                    "{} is paused, giving up on {}",
+                    self.config.name,
+                    self.detected_file.display()
+                );
+                self.abandon(started).await;
+                return None;
+            }
+
+            if is_downloading(&*self.fs, &self.detected_file) || !self.settled() {
                 continue;
             }
 
+            let permit = self.transfers.clone().acquire_owned().await.ok();
+            if !started && !self.checksum_matches().await {
+                self.record_error().await;
+                self.abandon(started).await;
+                return None;
+            }
             info!("Starting copy {}", self.detected_file.display());
+            if !started {
+                started = true;
+                self.record_started().await;
+                self.clear_complete_marker(&destination);
+                self.back_up(&destination);
+            }
+
+            let progress = Progress::new(&self.config, &self.detected_file);
+            // A timed out attempt's staging path may still be written to, so each gets its own
+            let attempt = Attempt::new(staging_path(&destination, attempts), permit);
+            attempts += 1;
+            let copy = async {
+                if self.fs.is_dir(&self.detected_file) {
+                    self.copy_dir_atomically(
+                        &self.detected_file,
+                        &destination,
+                        progress.as_ref(),
+                        &attempt,
+                    )
+                    .await
+                } else if self.deduplicate(&destination).await {
+                    Ok(())
+                } else {
+                    self.copy_file_atomically(&destination, progress, &attempt)
+                        .await
+                }
+            };
+            let copied = match self.config.copy_timeout() {
+                Some(timeout) => match tokio::time::timeout(timeout, copy).await {
+                    Ok(copied) => copied,
+                    Err(_) => Err(self.abort_timed_out_copy(&destination, &attempt, timeout)),
+                },
+                None => copy.await,
+            };
 
-            if self.detected_file.is_dir() {
-                match copy_dir_all(&self.detected_file, &destination).await {
-                    Ok(_) => file_moved = true,
-                    Err(e) => error!(
+            match copied {
+                Ok(_) => file_moved = true,
+                Err(e) => {
+                    error!(
                         "Error copying {} to {}: {}",
                         self.detected_file.display(),
                         destination.display(),
                         e
-                    ),
+                    );
+                    self.record_error().await;
+                    failures += 1;
+                    if self.config.quarantine_dir.is_some()
+                        && failures >= self.config.max_attempts()
+                    {
+                        self.quarantine(&format!(
+                            "Copying to {} failed {failures} times, last error: {e}",
+                            destination.display()
+                        ));
+                        self.abandon(started).await;
+                        return None;
+                    }
                 }
-            } else {
-                match copy_file(self.detected_file.clone(), destination.clone()).await {
-                    Ok(_) => file_moved = true,
-                    Err(e) => error!(
-                        "Error copying {} to {}: {}",
+            }
+        }
+
+        info!("{} moved successfully", self.detected_file.display());
+        if let Some(transcode) = &self.config.post_move_transcode
+            && self.fs.is_file(&destination)
+        {
+            // Transcodes count against max_concurrent like copies
+            let _permit = self.transfers.acquire().await.ok();
+            transcode::transcode(transcode, &destination, self.config.dry_run).await;
+        }
+        self.record(|status| status.in_flight -= 1).await;
+        self.finish(&destination);
+        status::complete(&self.config.name, &self.detected_file, destination.clone()).await;
+        MOVED_FILES.lock().await.insert(destination.clone());
+
+        if self.config.delete_source && self.config.dry_run {
+            info!(
+                "Dry run: would delete source {}",
+                self.detected_file.display()
+            );
+        } else if self.config.delete_source {
+            match self.config.delete_after_secs {
+                Some(delay) => {
+                    deletion::schedule(self.detected_file.clone(), Duration::from_secs(delay)).await
+                }
+                None => deletion::delete_source(&self.detected_file).await,
+            }
+        }
+
+        Some(destination)
+    }
+
+    // With backup_count, the file about to be overwritten is rotated into the backups first
+    fn back_up(&self, destination: &Path) {
+        let count = self.config.backup_count();
+        if count == 0 || destination == self.detected_file || !self.fs.is_file(destination) {
+            return;
+        }
+        if self.config.dry_run {
+            info!("Dry run: would back up {}", destination.display());
+            return;
+        }
+
+        let backup = |index: usize| {
+            let mut name = destination.as_os_str().to_os_string();
+            name.push(".bak");
+            if index > 0 {
+                name.push(format!(".{index}"));
+            }
+            PathBuf::from(name)
+        };
+        let oldest = backup(count - 1);
+        if self.fs.exists(&oldest)
+            && let Err(e) = self.fs.remove_file(&oldest)
+        {
+            warn!(
+                "Could not remove the old backup {}: {}",
+                oldest.display(),
+                e
+            );
+        }
+        for index in (0..count - 1).rev() {
+            let from = backup(index);
+            if self.fs.exists(&from)
+                && let Err(e) = self.fs.rename(&from, &backup(index + 1))
+            {
+                warn!("Could not rotate the backup {}: {}", from.display(), e);
+            }
+        }
+        match self.fs.rename(destination, &backup(0)) {
+            Ok(_) => info!("Backed up {} before overwriting it", destination.display()),
+            Err(e) => warn!(
+                "Could not back up {}, overwriting it anyway: {}",
+                destination.display(),
+                e
+            ),
+        }
+    }
+
+    // With verify_crc, whether the file's contents match the CRC32 in its name. Names without one,
+    // folders and sidecars (named after their video) aren't checked
+    async fn checksum_matches(&self) -> bool {
+        let Some(expected) = self
+            .metadata
+            .checksum
+            .as_deref()
+            .filter(|_| self.config.verify_crc && self.sidecar_of.is_none())
+            .and_then(|checksum| u32::from_str_radix(checksum, 16).ok())
+        else {
+            return true;
+        };
+        if self.fs.is_dir(&self.detected_file) {
+            return true;
+        }
+
+        let file = self.detected_file.clone();
+        let actual = match tokio::task::spawn_blocking(move || crc32(&file)).await {
+            Ok(Ok(actual)) => actual,
+            // Copying will run into the same problem and retry it
+            Ok(Err(e)) => {
+                warn!(
+                    "Could not check the CRC of {}: {}",
+                    self.detected_file.display(),
+                    e
+                );
+                return true;
+            }
+            Err(e) => {
+                warn!(
+                    "CRC check of {} failed: {}",
+                    self.detected_file.display(),
+                    e
+                );
+                return true;
+            }
+        };
+        if actual == expected {
+            info!("CRC of {} matches", self.detected_file.display());
+            return true;
+        }
+
+        let reason = format!("CRC mismatch, the name says {expected:08X} but it is {actual:08X}");
+        error!("Skipping {}: {reason}", self.detected_file.display());
+        self.quarantine(&reason);
+        false
+    }
+
+    // Sorting happens within one library, so the file is renamed rather than copied
+    async fn perform_sort(&self, destination: PathBuf) -> Option<PathBuf> {
+        let mut sorted = None;
+        if destination == self.detected_file {
+            info!("{} is already sorted", self.detected_file.display());
+            self.record(|status| status.queued -= 1).await;
+            sorted = Some(destination);
+        } else {
+            self.wait_until_ready().await;
+            self.record_started().await;
+            self.clear_complete_marker(&destination);
+            match self.fs.rename(&self.detected_file, &destination) {
+                Ok(_) => {
+                    info!(
+                        "Sorted {} into {}",
+                        self.detected_file.display(),
+                        destination.display()
+                    );
+                    self.finish(&destination);
+                    status::complete(&self.config.name, &self.detected_file, destination.clone())
+                        .await;
+                    sorted = Some(destination);
+                }
+                Err(e) => {
+                    error!(
+                        "Error sorting {} into {}: {}",
                         self.detected_file.display(),
                         destination.display(),
                         e
-                    ),
+                    );
+                    self.record_error().await;
+                    // A failed rename isn't retried, so there's nothing to wait for
+                    self.quarantine(&format!(
+                        "Sorting into {} failed: {e}",
+                        destination.display()
+                    ));
                 }
             }
+            self.record(|status| status.in_flight -= 1).await;
         }
 
-        info!("{} moved successfully", self.detected_file.display());
-        MOVED_FILES.lock().await.insert(destination);
+        DETECTED_FILES.lock().await.remove(&self.detected_file);
+        sorted
+    }
+
+    async fn copy(
+        &self,
+        source: PathBuf,
+        destination: PathBuf,
+        progress: Option<Arc<Progress>>,
+        attempt: &Attempt,
+    ) -> std::io::Result<u64> {
+        let fs = self.fs.clone();
+        let options = self.config.copy_options();
+        let attempt = attempt.clone();
+        attempt.copy_started();
+        tokio::task::spawn_blocking(move || {
+            let copied = fs.copy(&source, &destination, progress.as_deref(), options);
+            attempt.copy_returned(&*fs);
+            copied
+        })
+        .await?
+    }
+
+    // The blocking copy can't be interrupted and may stay stuck on the mount. It keeps the
+    // attempt's permit and staging path to itself until it returns, and the retry starts over in
+    // a fresh one
+    fn abort_timed_out_copy(
+        &self,
+        destination: &Path,
+        attempt: &Attempt,
+        timeout: Duration,
+    ) -> std::io::Error {
+        warn!(
+            "Copying {} to {} timed out after {timeout:?}, the destination mount may be hung",
+            self.detected_file.display(),
+            destination.display()
+        );
+        if attempt.abandon() {
+            let (fs, staging) = (self.fs.clone(), attempt.staging.clone());
+            tokio::task::spawn_blocking(move || remove_partial_copy(&*fs, &staging));
+        } else {
+            info!(
+                "Abandoned {}, it is removed once the stuck copy returns",
+                attempt.staging.display()
+            );
+        }
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("timed out after {timeout:?}"),
+        )
+    }
+
+    // Like copy_dir_atomically for a single file. Object stores only show an upload once it
+    // finished, so remote destinations are written directly
+    async fn copy_file_atomically(
+        &self,
+        destination: &Path,
+        progress: Option<Arc<Progress>>,
+        attempt: &Attempt,
+    ) -> std::io::Result<()> {
+        let source = self.detected_file.clone();
+        if self.config.has_remote_destination() {
+            self.copy(source, destination.to_path_buf(), progress, attempt)
+                .await?;
+            permissions::apply(destination, self.config.file_mode);
+            return Ok(());
+        }
+
+        let staging = &attempt.staging;
+        // Left behind by a crash mid-copy
+        if self.fs.is_file(staging) {
+            self.fs.remove_file(staging)?;
+        }
+
+        let copied = match self.copy(source, staging.clone(), progress, attempt).await {
+            Ok(_) => self.fs.rename(staging, destination),
+            Err(e) => Err(e),
+        };
+        if copied.is_err()
+            && self.fs.exists(staging)
+            && let Err(e) = self.fs.remove_file(staging)
+        {
+            warn!(
+                "Could not clean up the partial copy {}: {}",
+                staging.display(),
+                e
+            );
+        }
+        copied.map(|_| permissions::apply(destination, self.config.file_mode))
+    }
+
+    // Copies into a hidden sibling that is renamed into place once the whole tree made it, so a
+    // failure part way through never leaves a half-copied folder that looks complete. An existing
+    // folder at the destination is replaced
+    async fn copy_dir_atomically(
+        &self,
+        src: &Path,
+        dst: &Path,
+        progress: Option<&Arc<Progress>>,
+        attempt: &Attempt,
+    ) -> std::io::Result<()> {
+        let staging = &attempt.staging;
+        // Left behind by a crash mid-copy
+        if self.fs.is_dir(staging) {
+            self.fs.remove_dir_all(staging)?;
+        }
+
+        let copied = match self.copy_dir_all(src, staging, progress, attempt).await {
+            Ok(()) if self.fs.is_dir(dst) => self
+                .fs
+                .remove_dir_all(dst)
+                .and_then(|_| self.fs.rename(staging, dst)),
+            Ok(()) => self.fs.rename(staging, dst),
+            Err(e) => Err(e),
+        };
+        if copied.is_err()
+            && self.fs.exists(staging)
+            && let Err(e) = self.fs.remove_dir_all(staging)
+        {
+            warn!(
+                "Could not clean up the partial copy {}: {}",
+                staging.display(),
+                e
+            );
+        }
+        copied
+    }
+
+    async fn copy_dir_all(
+        &self,
+        src: &Path,
+        dst: &Path,
+        progress: Option<&Arc<Progress>>,
+        attempt: &Attempt,
+    ) -> std::io::Result<()> {
+        self.fs.create_dir_all(dst)?;
+        permissions::apply(dst, self.config.dir_mode);
+        for path in self.fs.read_dir(src)? {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            if self.config.is_ignored_name(file_name) {
+                continue;
+            }
+
+            let destination = dst.join(file_name);
+            if self.fs.is_dir(&path) {
+                Box::pin(self.copy_dir_all(&path, &destination, progress, attempt)).await?;
+            } else {
+                self.copy(
+                    path.clone(),
+                    destination.clone(),
+                    progress.cloned(),
+                    attempt,
+                )
+                .await?;
+                permissions::apply(&destination, self.config.file_mode);
+            }
+        }
+        Ok(())
+    }
+
+    // Bookkeeping once the file is in its final place
+    fn finish(&self, destination: &Path) {
+        if self.config.write_provenance_xattr {
+            provenance::record(&self.detected_file, destination);
+        }
+        self.prune(destination);
+        self.write_complete_marker(destination);
+    }
+
+    // The folder the file's marker goes in: a copied folder holds its own, a file marks its parent
+    fn complete_marker(&self, destination: &Path) -> Option<PathBuf> {
+        let marker = self.config.complete_marker.as_ref()?;
+        // Before the move only the source is there, afterwards (for sorts) only the destination
+        let folder = if self.fs.is_dir(&self.detected_file) || self.fs.is_dir(destination) {
+            destination
+        } else {
+            destination.parent()?
+        };
+        Some(folder.join(marker))
+    }
+
+    // A marker left by an earlier move would claim the folder is complete while this one runs
+    fn clear_complete_marker(&self, destination: &Path) {
+        let Some(marker) = self.complete_marker(destination) else {
+            return;
+        };
+        match self.fs.remove_file(&marker) {
+            Ok(_) => info!("Removed stale marker {}", marker.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Error removing marker {}: {}", marker.display(), e),
+        }
+    }
+
+    fn write_complete_marker(&self, destination: &Path) {
+        let Some(marker) = self.complete_marker(destination) else {
+            return;
+        };
+        match self.fs.write(&marker, &[]) {
+            Ok(_) => permissions::apply(&marker, self.config.file_mode),
+            Err(e) => error!("Error writing marker {}: {}", marker.display(), e),
+        }
+    }
+
+    fn prune(&self, destination: &Path) {
+        let Some(keep) = self.config.keep_latest else {
+            return;
+        };
+
+        // Only episodes count, a new subtitle shouldn't push an episode out
+        if self.sidecar_of.is_none() && metadata::is_video(destination) {
+            prune::keep_latest(&self.config, destination, &self.metadata, keep);
+        }
+    }
+
+    // Returns true when an identical library file made the copy unnecessary
+    async fn deduplicate(&self, destination: &Path) -> bool {
+        if matches!(self.config.dedup, DedupPolicy::Off) {
+            return false;
+        }
+
+        let Some(duplicate) =
+            dedup::find_duplicate(self.detected_file.clone(), self.destination_root()).await
+        else {
+            return false;
+        };
+
+        match self.config.dedup {
+            DedupPolicy::Hardlink if duplicate != destination => {
+                match self.fs.hard_link(&duplicate, destination) {
+                    Ok(_) => {
+                        info!(
+                            "{} is identical to {}, hardlinked instead of copying",
+                            self.detected_file.display(),
+                            duplicate.display()
+                        );
+                        true
+                    }
+                    Err(e) => {
+                        error!(
+                            "Could not hardlink {} to {}, copying instead: {}",
+                            duplicate.display(),
+                            destination.display(),
+                            e
+                        );
+                        false
+                    }
+                }
+            }
+            _ => {
+                info!(
+                    "{} is identical to {}, skipping",
+                    self.detected_file.display(),
+                    duplicate.display()
+                );
+                true
+            }
+        }
+    }
+
+    async fn record(&self, change: impl FnOnce(&mut PathStatus)) {
+        status::update(&self.config.name, change).await;
+    }
+
+    async fn record_error(&self) {
+        status::record_error(&self.config.name, self.config.pause_after_errors).await;
+    }
+
+    // Moves a file that can't be organized into quarantine_dir, next to a `.error.txt` with the
+    // reason, so it stops being retried. Without quarantine_dir it stays in the source
+    fn quarantine(&self, reason: &str) {
+        let Some(quarantine_dir) = &self.config.quarantine_dir else {
+            return;
+        };
+        let Some(file_name) = self.detected_file.file_name() else {
+            return;
+        };
+
+        if let Err(e) = self.fs.create_dir_all(quarantine_dir) {
+            error!("Could not create {}: {}", quarantine_dir.display(), e);
+            return;
+        }
+        let mut quarantined = quarantine_dir.join(file_name);
+        if self.fs.exists(&quarantined) {
+            quarantined = free_path(&*self.fs, &quarantined, &HashSet::new());
+        }
+
+        if let Err(e) = self.fs.rename(&self.detected_file, &quarantined) {
+            error!(
+                "Could not quarantine {} to {}: {}",
+                self.detected_file.display(),
+                quarantined.display(),
+                e
+            );
+            return;
+        }
+        warn!(
+            "Quarantined {} to {}: {reason}",
+            self.detected_file.display(),
+            quarantined.display()
+        );
+
+        let mut error_file = quarantined.into_os_string();
+        error_file.push(".error.txt");
+        let contents = format!("{}\n{reason}\n", self.detected_file.display());
+        if let Err(e) = self.fs.write(Path::new(&error_file), contents.as_bytes()) {
+            error!(
+                "Could not write {}: {}",
+                Path::new(&error_file).display(),
+                e
+            );
+        }
+    }
+
+    // Forgets the file so a later event can pick it up again
+    async fn abandon(&self, started: bool) {
+        if started {
+            self.record(|status| status.in_flight -= 1).await;
+        } else {
+            self.record(|status| status.queued -= 1).await;
+        }
+        DETECTED_FILES.lock().await.remove(&self.detected_file);
+    }
+
+    // Whether settle_secs passed without writes since the file was detected or last changed
+    fn settled(&self) -> bool {
+        let (Some(settle), Some(activity)) = (self.config.settle(), &self.activity) else {
+            return true;
+        };
+        let last_change = *activity
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        last_change.elapsed() >= settle
+    }
+
+    async fn record_started(&self) {
+        let current_file = self.detected_file.clone();
+        self.record(|status| {
+            status.queued -= 1;
+            status.in_flight += 1;
+            status.start(current_file);
+        })
+        .await;
+    }
+
+    // Falls back to polling every wait_time when the backend never reports a close-write
+    async fn wait_until_ready(&self) {
+        match &self.ready {
+            Some(ready) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(self.wait_time) => {}
+                    _ = ready.notified() => {}
+                }
+            }
+            None => tokio::time::sleep(self.wait_time).await,
+        }
     }
 }
 
-fn get_title(detected_file: &Path) -> Option<String> {
-    anitomy::parse(detected_file.file_name().unwrap().to_str().unwrap())
-        .iter()
-        .find(|element| element.kind() == ElementKind::Title)
-        .map(|element| element.value().to_string())
+// Counts the file in ACTIVE_MOVES for as long as its mover runs, whichever way start returns
+struct ActiveMove(PathBuf);
+
+impl ActiveMove {
+    fn new(file: &Path) -> Self {
+        *active_moves().entry(file.to_path_buf()).or_default() += 1;
+        Self(file.to_path_buf())
+    }
+}
+
+impl Drop for ActiveMove {
+    fn drop(&mut self) {
+        let mut active = active_moves();
+        if let Some(count) = active.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.0);
+            }
+        }
+    }
+}
+
+// Where a file or folder is copied to before it is renamed into place. Retries after a timeout
+// get their own, since the timed out copy may still be writing to the earlier one
+fn staging_path(destination: &Path, attempt: usize) -> PathBuf {
+    let file_name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    match attempt {
+        0 => destination.with_file_name(format!(".{file_name}.copying")),
+        _ => destination.with_file_name(format!(".{file_name}.{attempt}.copying")),
+    }
+}
+
+fn remove_partial_copy(fs: &dyn FileSystem, staging: &Path) {
+    let removed = if fs.is_dir(staging) {
+        fs.remove_dir_all(staging)
+    } else {
+        fs.remove_file(staging)
+    };
+    if let Err(e) = removed
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!(
+            "Could not remove the partial copy {}: {}",
+            staging.display(),
+            e
+        );
+    }
+}
+
+// One try at copying a file or folder. The blocking copies it spawns can't be interrupted, so
+// each holds on to the max_concurrent permit until it actually returns, and once the try was
+// abandoned the last of them to return removes the staging path
+#[derive(Clone)]
+struct Attempt {
+    staging: PathBuf,
+    _permit: Option<Arc<OwnedSemaphorePermit>>,
+    state: Arc<std::sync::Mutex<AttemptState>>,
+}
+
+#[derive(Default)]
+struct AttemptState {
+    running: usize,
+    abandoned: bool,
+}
+
+impl Attempt {
+    fn new(staging: PathBuf, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Attempt {
+            staging,
+            _permit: permit.map(Arc::new),
+            state: Arc::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, AttemptState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn copy_started(&self) {
+        self.state().running += 1;
+    }
+
+    fn copy_returned(&self, fs: &dyn FileSystem) {
+        let mut state = self.state();
+        state.running -= 1;
+        if state.abandoned && state.running == 0 {
+            remove_partial_copy(fs, &self.staging);
+        }
+    }
+
+    // Whether no copy is running anymore, so the staging path can be removed right away
+    fn abandon(&self) -> bool {
+        let mut state = self.state();
+        state.abandoned = true;
+        state.running == 0
+    }
+}
+
+// First of `name (1).ext`, `name (2).ext`, ... that doesn't exist yet
+pub(crate) fn free_path(fs: &dyn FileSystem, path: &Path, reserved: &HashSet<PathBuf>) -> PathBuf {
+    let (stem, extension) = match path.extension() {
+        Some(extension) if !fs.is_dir(path) => (
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            format!(".{}", extension.to_string_lossy()),
+        ),
+        _ => (
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            String::new(),
+        ),
+    };
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){extension}")))
+        .find(|candidate| !fs.exists(candidate) && !reserved.contains(candidate))
+        .unwrap()
+}
+
+// A sidecar has a configured sidecar extension and shares its basename with a video next to it,
+// e.g. `episode.en.srt` next to `episode.mkv`
+fn find_sidecar_video(fs: &dyn FileSystem, config: &PathConfig, file: &Path) -> Option<PathBuf> {
+    let extension = file.extension()?.to_str()?;
+    let is_sidecar = config.sidecar_extensions.iter().any(|sidecar| {
+        sidecar
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(extension)
+    });
+    if !is_sidecar {
+        return None;
+    }
+
+    let file_name = file.file_name()?.to_str()?;
+    fs.read_dir(file.parent()?)
+        .ok()?
+        .into_iter()
+        .filter(|path| metadata::is_video(path))
+        .find(|video| {
+            video
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| file_name.starts_with(&format!("{stem}.")))
+        })
+}
+
+fn crc32(path: &Path) -> std::io::Result<u32> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.finalize()),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
 }
 
-fn create_folder(folder: &Path) {
-    if folder.exists() {
+// The first moment of the year after, None before 1970
+fn end_of_year(year: u64) -> Option<SystemTime> {
+    let leap_days = |year: u64| year / 4 - year / 100 + year / 400;
+    let next = year.checked_add(1)?;
+    let days = 365 * next.checked_sub(1970)? + leap_days(next - 1) - leap_days(1969);
+    UNIX_EPOCH.checked_add(Duration::from_secs(days.checked_mul(24 * 60 * 60)?))
+}
+
+fn create_folder(fs: &dyn FileSystem, root: &Path, folder: &Path, dir_mode: Option<u32>) {
+    fix_folder_case(fs, root, folder);
+    if fs.exists(folder) {
         return;
     }
 
-    match std::fs::create_dir_all(folder) {
-        Ok(_) => {}
+    match fs.create_dir_all(folder) {
+        Ok(_) => permissions::apply_below(root, folder, dir_mode),
         Err(e) => {
             error!("Could not create folder {}: {}", folder.display(), e);
         }
     }
 }
 
-async fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
-    std::fs::create_dir_all(&dst)?;
-    for entry in std::fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
-            Box::pin(copy_dir_all(
-                entry.path(),
-                dst.as_ref().join(entry.file_name()),
-            ))
-            .await?;
-        } else {
-            copy_file(entry.path(), dst.as_ref().join(entry.file_name())).await?;
+// On case-insensitive filesystems (macOS, Windows) `Show Name` already "exists" when `show name`
+// does, so a corrected title would keep landing in the old casing. Folders below `root` whose
+// name only differs in case are renamed, through a temporary name since renaming straight to the
+// new casing is a no-op there. Case-sensitive filesystems list the exact name or don't claim it
+// exists, so distinct folders are left alone
+fn fix_folder_case(fs: &dyn FileSystem, root: &Path, folder: &Path) {
+    let Ok(relative) = folder.strip_prefix(root) else {
+        return;
+    };
+
+    let mut parent = root.to_path_buf();
+    for component in relative.components() {
+        let wanted = parent.join(component);
+        if !fs.exists(&wanted) {
+            return;
+        }
+
+        let name = component.as_os_str().to_string_lossy();
+        let lowercase = name.to_lowercase();
+        let existing = fs.read_dir(&parent).ok().and_then(|entries| {
+            if entries
+                .iter()
+                .any(|entry| entry.file_name() == Some(component.as_os_str()))
+            {
+                return None;
+            }
+            entries.into_iter().find(|entry| {
+                entry
+                    .file_name()
+                    .is_some_and(|entry| entry.to_string_lossy().to_lowercase() == lowercase)
+            })
+        });
+        if let Some(existing) = existing {
+            let temporary = parent.join(format!("{name}.aorg-rename"));
+            match fs
+                .rename(&existing, &temporary)
+                .and_then(|_| fs.rename(&temporary, &wanted))
+            {
+                Ok(_) => info!("Renamed {} to {}", existing.display(), wanted.display()),
+                Err(e) => error!(
+                    "Could not rename {} to {}: {}",
+                    existing.display(),
+                    wanted.display(),
+                    e
+                ),
+            }
         }
+
+        parent = wanted;
     }
-    Ok(())
 }
 
-fn is_downloading(file: &Path) -> bool {
-    if file.is_dir() {
-        std::fs::read_dir(file)
+// Entries that vanish mid-scan are skipped, the next check sees the settled state
+fn is_downloading(fs: &dyn FileSystem, file: &Path) -> bool {
+    if !fs.is_dir(file) {
+        return false;
+    }
+
+    let Ok(entries) = fs.read_dir(file) else {
+        return false;
+    };
+
+    entries
+        .iter()
+        .any(|entry| entry.ends_with(".partial") || is_downloading(fs, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use crate::copy::CopyOptions;
+    use crate::filesystem::{FileMetadata, MemoryFileSystem};
+
+    // A Copy path from the file's folder to /library, with `options` added to it
+    fn path_config(options: &str, file: &str) -> PathConfig {
+        let config = Config::from_toml(
+            &format!(
+                r#"
+                [[paths]]
+                name = "library"
+                watcher_type = "Copy"
+                source = '{}'
+                destination = "/library"
+                place_in_sub = true
+                {options}
+                "#,
+                Path::new(file).parent().unwrap().display()
+            ),
+            Path::new("/config.toml"),
+        )
+        .unwrap();
+        config.paths[0].clone()
+    }
+
+    fn mover_for(fs: &Arc<MemoryFileSystem>, config: PathConfig, file: &str) -> Mover {
+        Mover::new(
+            Arc::new(config),
+            fs.clone(),
+            PathBuf::from(file),
+            None,
+            Arc::new(Semaphore::new(1)),
+        )
+    }
+
+    fn mover(fs: &Arc<MemoryFileSystem>, options: &str, file: &str) -> Mover {
+        mover_for(fs, path_config(options, file), file)
+    }
+
+    #[test]
+    fn quarantine_moves_the_file_next_to_its_reason() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show - 01.mkv", "corrupt");
+        let mover = mover(
+            &fs,
+            r#"quarantine_dir = "/quarantine""#,
+            "/source/Show - 01.mkv",
+        );
+
+        mover.quarantine("CRC mismatch");
+
+        assert!(!fs.exists(Path::new("/source/Show - 01.mkv")));
+        assert_eq!(
+            fs.contents("/quarantine/Show - 01.mkv").as_deref(),
+            Some(&b"corrupt"[..])
+        );
+        assert_eq!(
+            fs.contents("/quarantine/Show - 01.mkv.error.txt")
+                .as_deref(),
+            Some(&b"/source/Show - 01.mkv\nCRC mismatch\n"[..])
+        );
+    }
+
+    #[test]
+    fn complete_marker_is_written_next_to_the_file() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show - 01.mkv", "episode");
+        fs.create_dir_all(Path::new("/library/Show")).unwrap();
+        let mover = mover(
+            &fs,
+            r#"complete_marker = ".complete""#,
+            "/source/Show - 01.mkv",
+        );
+
+        mover.write_complete_marker(Path::new("/library/Show/Show - 01.mkv"));
+
+        assert_eq!(
+            fs.contents("/library/Show/.complete").as_deref(),
+            Some(&b""[..])
+        );
+    }
+
+    #[cfg(any(target_os = "macos", windows))]
+    #[test]
+    fn folder_differing_only_in_case_is_renamed() {
+        let root = std::env::temp_dir().join(format!(
+            "anime-organizer-folder-case-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("show name")).unwrap();
+        std::fs::write(root.join("show name").join("Show Name - 01.mkv"), "episode").unwrap();
+
+        fix_folder_case(&RealFileSystem, &root, &root.join("Show Name"));
+
+        let names: Vec<_> = std::fs::read_dir(&root)
             .unwrap()
-            .map(|e| e.unwrap())
-            .any(|e| e.path().ends_with(".partial"))
-            || std::fs::read_dir(file)
-                .unwrap()
-                .map(|e| e.unwrap())
-                .filter(|e| e.file_type().unwrap().is_dir())
-                .any(|e| is_downloading(e.path().as_path()))
-    } else {
-        false
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["Show Name"]);
+        assert!(root.join("Show Name").join("Show Name - 01.mkv").is_file());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Case-sensitive filesystems, like MemoryFileSystem, keep both casings apart
+    #[test]
+    fn folders_differing_in_case_are_left_alone_when_distinct() {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/library/show name/Show Name - 01.mkv", "episode");
+
+        fix_folder_case(&fs, Path::new("/library"), Path::new("/library/Show Name"));
+
+        assert!(fs.exists(Path::new("/library/show name/Show Name - 01.mkv")));
+        assert!(!fs.exists(Path::new("/library/Show Name")));
+    }
+
+    #[tokio::test]
+    async fn sources_racing_for_a_destination_both_keep_their_file() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/downloads/Race - 01.mkv", "from downloads");
+        fs.add_file("/torrents/Race - 01.mkv", "from torrents");
+        fs.create_dir_all(Path::new("/library/Race")).unwrap();
+        let (first, second) = (
+            mover(&fs, "", "/downloads/Race - 01.mkv"),
+            mover(&fs, "", "/torrents/Race - 01.mkv"),
+        );
+
+        let destination = PathBuf::from("/library/Race/Race - 01.mkv");
+        let (first_destination, second_destination) = tokio::join!(
+            first.resolve_conflict(destination.clone()),
+            second.resolve_conflict(destination.clone())
+        );
+        let (first_destination, second_destination) =
+            (first_destination.unwrap(), second_destination.unwrap());
+        assert_ne!(first_destination, second_destination);
+        for (mover, destination) in [(&first, &first_destination), (&second, &second_destination)] {
+            fs.copy(
+                &mover.detected_file,
+                destination,
+                None,
+                CopyOptions::default(),
+            )
+            .unwrap();
+        }
+
+        let mut contents = [&first_destination, &second_destination]
+            .map(|destination| fs.contents(destination).unwrap());
+        contents.sort();
+        assert_eq!(
+            contents,
+            [b"from downloads".to_vec(), b"from torrents".to_vec()]
+        );
+
+        let mut reserved = RESERVED_DESTINATIONS.lock().await;
+        reserved.remove(&first_destination);
+        reserved.remove(&second_destination);
+    }
+
+    #[test]
+    fn destination_is_the_title_folder_with_place_in_sub() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        let file = "/source/[Group] Show Name - 01 [1080p].mkv";
+        fs.add_file(file, "episode");
+
+        assert_eq!(
+            mover(&fs, "", file).compute_destination(None),
+            PathBuf::from("/library/Show Name/[Group] Show Name - 01 [1080p].mkv")
+        );
+
+        let mut config = path_config("", file);
+        config.place_in_sub = false;
+        assert_eq!(
+            mover_for(&fs, config, file).compute_destination(None),
+            PathBuf::from("/library/[Group] Show Name - 01 [1080p].mkv")
+        );
+    }
+
+    #[test]
+    fn destination_follows_title_rules_and_templates() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        let file = "/source/Odd Naming S1E3 final.mkv";
+        fs.add_file(file, "episode");
+        let mover = mover(
+            &fs,
+            r#"
+            folder_template = "{title}/Season {season:02}"
+            rename_template = "{title} - S{season:02}E{episode:02}"
+
+            [[paths.title_rules]]
+            pattern = '^(?<title>.+?) S(?<season>\d+)E(?<episode>\d+)'
+            title_group = "title"
+            season_group = "season"
+            episode_group = "episode"
+            "#,
+            file,
+        );
+
+        assert_eq!(
+            mover.compute_destination(None),
+            PathBuf::from("/library/Odd Naming/Season 01/Odd Naming - S01E03.mkv")
+        );
+        // The resolver's answer replaces the title folder
+        assert_eq!(
+            mover.compute_destination(Some(Path::new("Resolved"))),
+            PathBuf::from("/library/Resolved/Odd Naming - S01E03.mkv")
+        );
+    }
+
+    #[test]
+    fn destination_uses_language_and_subtitle_folders() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        let file = "/source/[Group] Show Name - 01 [1080p].mkv";
+        fs.add_file(file, "episode");
+        let options = r#"
+            language_folders = { "Dual Audio" = "Dub", English = "/anime-dub" }
+            subtitle_folders = { Hardsubs = "Hardsubbed" }
+            "#;
+
+        let mut mover = mover(&fs, options, file);
+        mover.metadata.languages = vec!["Dual Audio".to_string()];
+        mover.metadata.subtitles = vec!["Hardsubs".to_string()];
+        assert_eq!(
+            mover.compute_destination(None),
+            PathBuf::from("/library/Dub/Hardsubbed/Show Name/[Group] Show Name - 01 [1080p].mkv")
+        );
+
+        mover.metadata.languages = vec!["english".to_string()];
+        mover.metadata.subtitles.clear();
+        assert_eq!(
+            mover.compute_destination(None),
+            PathBuf::from("/anime-dub/Show Name/[Group] Show Name - 01 [1080p].mkv")
+        );
+    }
+
+    // A MemoryFileSystem whose first copy writes part of the file and then hangs until `release`
+    // fires, like a copy stuck on a hung mount
+    struct HungCopy {
+        fs: MemoryFileSystem,
+        release: std::sync::Mutex<Option<std::sync::mpsc::Receiver<()>>>,
+    }
+
+    impl FileSystem for HungCopy {
+        fn copy(
+            &self,
+            source: &Path,
+            destination: &Path,
+            progress: Option<&Progress>,
+            options: CopyOptions,
+        ) -> std::io::Result<u64> {
+            let hung = self.release.lock().unwrap().take();
+            if let Some(release) = hung {
+                self.fs.write(destination, b"partial")?;
+                let _ = release.recv();
+            }
+            self.fs.copy(source, destination, progress, options)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+            self.fs.rename(from, to)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.create_dir_all(path)
+        }
+
+        fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_file(path)
+        }
+
+        fn remove_dir(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_dir(path)
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_dir_all(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+            self.fs.write(path, contents)
+        }
+
+        fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+            self.fs.hard_link(original, link)
+        }
+
+        fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+            self.fs.read_dir(path)
+        }
+
+        fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata> {
+            self.fs.metadata(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn timed_out_copy_keeps_its_permit_and_staging_path() {
+        let (release, hung) = std::sync::mpsc::channel();
+        let fs = Arc::new(HungCopy {
+            fs: MemoryFileSystem::new(),
+            release: std::sync::Mutex::new(Some(hung)),
+        });
+        fs.fs.add_file("/source/Show - 01.mkv", "episode");
+        fs.fs.create_dir_all(Path::new("/library/Show")).unwrap();
+        let transfers = Arc::new(Semaphore::new(1));
+        let mover = Mover::new(
+            Arc::new(path_config("", "/source/Show - 01.mkv")),
+            fs.clone(),
+            PathBuf::from("/source/Show - 01.mkv"),
+            None,
+            transfers.clone(),
+        );
+        let destination = Path::new("/library/Show/Show - 01.mkv");
+
+        let timeout = Duration::from_millis(50);
+        let attempt = Attempt::new(
+            staging_path(destination, 0),
+            transfers.clone().try_acquire_owned().ok(),
+        );
+        let copy = mover.copy_file_atomically(destination, None, &attempt);
+        assert!(tokio::time::timeout(timeout, copy).await.is_err());
+        mover.abort_timed_out_copy(destination, &attempt, timeout);
+        drop(attempt);
+
+        // The stuck copy still counts against max_concurrent and never reaches the destination
+        assert_eq!(transfers.available_permits(), 0);
+        assert!(!fs.exists(destination));
+
+        let retry = Attempt::new(staging_path(destination, 1), None);
+        mover
+            .copy_file_atomically(destination, None, &retry)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.fs.contents(destination).as_deref(),
+            Some(&b"episode"[..])
+        );
+        assert!(!fs.exists(&staging_path(destination, 1)));
+
+        // Once it returns, the stuck copy gives the permit back and removes what it wrote
+        release.send(()).unwrap();
+        let permit = tokio::time::timeout(Duration::from_secs(5), transfers.acquire())
+            .await
+            .unwrap();
+        assert!(permit.is_ok());
+        assert!(!fs.exists(&staging_path(destination, 0)));
+        assert_eq!(
+            fs.fs.contents(destination).as_deref(),
+            Some(&b"episode"[..])
+        );
+    }
+
+    #[test]
+    fn fuzzy_title_match_finds_templated_title_folders() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        let file = "/source/Show name - 01.mkv";
+        fs.add_file(file, "episode");
+        fs.create_dir_all(Path::new("/library/S/Show Name (2020)"))
+            .unwrap();
+        fs.create_dir_all(Path::new("/library/F/Fate Zero (2011)"))
+            .unwrap();
+        let mut mover = mover(
+            &fs,
+            r#"
+            folder_template = "{title:first_letter}/{title} ({year})"
+            fuzzy_title_threshold = 0.8
+            "#,
+            file,
+        );
+        mover.metadata.title = Some("Show name".to_string());
+        mover.metadata.year = Some("2020".to_string());
+
+        let destination = mover.compute_destination(None);
+        assert_eq!(
+            destination,
+            PathBuf::from("/library/S/Show name (2020)/Show name - 01.mkv")
+        );
+        assert_eq!(
+            mover.match_existing_title(destination),
+            PathBuf::from("/library/S/Show Name (2020)/Show name - 01.mkv")
+        );
+
+        // A `/` in the title makes it two folders, neither of which is the title folder
+        mover.metadata.title = Some("Fate/Zero".to_string());
+        mover.metadata.year = Some("2011".to_string());
+        let destination = mover.compute_destination(None);
+        assert_eq!(mover.match_existing_title(destination.clone()), destination);
     }
 }
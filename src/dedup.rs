@@ -0,0 +1,82 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use walkdir::WalkDir;
+
+struct IndexedFile {
+    size: u64,
+    modified: SystemTime,
+    hash: blake3::Hash,
+}
+
+lazy_static! {
+    // Hashes of library files, reused until the file's size or mtime changes
+    static ref HASH_INDEX: Mutex<HashMap<PathBuf, IndexedFile>> = Mutex::new(HashMap::new());
+}
+
+// Looks for a file in the library with the same contents as `file`. Only files of identical
+// size are hashed, so most lookups never read anything but metadata.
+pub async fn find_duplicate(file: PathBuf, library: PathBuf) -> Option<PathBuf> {
+    tokio::task::spawn_blocking(move || find_duplicate_blocking(&file, &library))
+        .await
+        .ok()
+        .flatten()
+}
+
+fn find_duplicate_blocking(file: &Path, library: &Path) -> Option<PathBuf> {
+    let size = std::fs::metadata(file).ok()?.len();
+    let mut source_hash = None;
+
+    for entry in WalkDir::new(library).into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() || entry.path() == file {
+            continue;
+        }
+
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.len() != size {
+            continue;
+        }
+
+        let expected = match source_hash {
+            Some(hash) => hash,
+            None => *source_hash.insert(hash_file(file).ok()?),
+        };
+        if cached_hash(entry.path(), &metadata) == Some(expected) {
+            return Some(entry.into_path());
+        }
+    }
+
+    None
+}
+
+fn cached_hash(path: &Path, metadata: &Metadata) -> Option<blake3::Hash> {
+    let modified = metadata.modified().ok()?;
+    if let Some(indexed) = HASH_INDEX.blocking_lock().get(path)
+        && indexed.size == metadata.len()
+        && indexed.modified == modified
+    {
+        return Some(indexed.hash);
+    }
+
+    let hash = hash_file(path).ok()?;
+    HASH_INDEX.blocking_lock().insert(
+        path.to_path_buf(),
+        IndexedFile {
+            size: metadata.len(),
+            modified,
+            hash,
+        },
+    );
+    Some(hash)
+}
+
+fn hash_file(path: &Path) -> std::io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(std::fs::File::open(path)?)?;
+    Ok(hasher.finalize())
+}
@@ -0,0 +1,62 @@
+use crate::DETECTED_FILES;
+use crate::state::{self, PendingDeletion};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{Instrument, error, info};
+
+pub async fn schedule(path: PathBuf, delay: Duration) {
+    info!("Deleting {} in {}s", path.display(), delay.as_secs());
+    let deletion = PendingDeletion {
+        path,
+        delete_at: unix_now() + delay.as_secs(),
+    };
+
+    state::update(|state| state.pending_deletions.push(deletion.clone())).await;
+    tokio::spawn(delete_when_due(deletion).in_current_span());
+}
+
+// Picks up deletions scheduled before the last shutdown
+pub async fn resume_pending() {
+    for deletion in state::pending_deletions().await {
+        tokio::spawn(delete_when_due(deletion));
+    }
+}
+
+pub async fn delete_source(path: &Path) {
+    let result = if path.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    };
+
+    match result {
+        Ok(_) => info!("Deleted source {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Source {} was already deleted", path.display())
+        }
+        Err(e) => error!("Could not delete source {}: {}", path.display(), e),
+    }
+
+    // The same release can now be downloaded and organized again
+    DETECTED_FILES.lock().await.remove(path);
+}
+
+async fn delete_when_due(deletion: PendingDeletion) {
+    let remaining = deletion.delete_at.saturating_sub(unix_now());
+    tokio::time::sleep(Duration::from_secs(remaining)).await;
+
+    delete_source(&deletion.path).await;
+    state::update(|state| {
+        state
+            .pending_deletions
+            .retain(|pending| pending.path != deletion.path)
+    })
+    .await;
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
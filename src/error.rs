@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+// What the library's entry points fail with. The binary only shows them, so everything converts
+// into anyhow::Error as usual
+#[derive(Debug, thiserror::Error)]
+pub enum OrganizerError {
+    // Reading the config, or an option in it that doesn't hold up
+    #[error("invalid config: {0:#}")]
+    Config(anyhow::Error),
+    // The config file isn't valid TOML or doesn't match the options
+    #[error("could not parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    // Setting up the filesystem watcher on a source
+    #[error("could not watch {}: {source}", path.display())]
+    Watch {
+        path: PathBuf,
+        #[source]
+        source: notify::Error,
+    },
+    // Reading a source or writing a destination outside of a mover, which retries on its own
+    #[error("could not copy {}: {source}", path.display())]
+    Copy {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl OrganizerError {
+    pub(crate) fn watch(path: impl Into<PathBuf>) -> impl FnOnce(notify::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Watch { path, source }
+    }
+
+    pub(crate) fn copy(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Copy { path, source }
+    }
+}
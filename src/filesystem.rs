@@ -0,0 +1,347 @@
+use crate::copy::{self, CopyOptions, Progress};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// The filesystem operations movers depend on, so organizing can run against something other than
+// the real disk, e.g. MemoryFileSystem in an embedder's tests
+pub trait FileSystem: Send + Sync {
+    fn copy(
+        &self,
+        source: &Path,
+        destination: &Path,
+        progress: Option<&Progress>,
+        options: CopyOptions,
+    ) -> std::io::Result<u64>;
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()>;
+    fn remove_file(&self, path: &Path) -> std::io::Result<()>;
+    // Only an empty folder
+    fn remove_dir(&self, path: &Path) -> std::io::Result<()>;
+    // The folder and everything inside it
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()>;
+    // Creates or replaces a (small) file, such as a marker or a note next to a file
+    fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()>;
+    fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()>;
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>>;
+    fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_dir)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| !metadata.is_dir)
+    }
+
+    // The link itself, metadata follows it
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FileMetadata {
+    pub is_dir: bool,
+    pub len: u64,
+    // None where the filesystem doesn't keep it
+    pub modified: Option<SystemTime>,
+}
+
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn copy(
+        &self,
+        source: &Path,
+        destination: &Path,
+        progress: Option<&Progress>,
+        options: CopyOptions,
+    ) -> std::io::Result<u64> {
+        copy::copy_blocking(source, destination, progress, options)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+        std::fs::hard_link(original, link)
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+}
+
+// Files and folders kept in a map, for exercising organizing logic without touching the disk
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+}
+
+enum MemoryEntry {
+    File(Vec<u8>),
+    Dir,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Creates the file along with any missing parent folders
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            let _ = self.create_dir_all(parent);
+        }
+        self.lock()
+            .insert(path.to_path_buf(), MemoryEntry::File(contents.into()));
+    }
+
+    pub fn contents(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        match self.lock().get(path.as_ref()) {
+            Some(MemoryEntry::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn copy(
+        &self,
+        source: &Path,
+        destination: &Path,
+        progress: Option<&Progress>,
+        _options: CopyOptions,
+    ) -> std::io::Result<u64> {
+        let mut entries = self.lock();
+        let Some(MemoryEntry::File(contents)) = entries.get(source) else {
+            return Err(not_found(source));
+        };
+        let contents = contents.clone();
+        check_parent(&entries, destination)?;
+
+        let len = contents.len() as u64;
+        entries.insert(destination.to_path_buf(), MemoryEntry::File(contents));
+        if let Some(progress) = progress {
+            progress.advance(len);
+        }
+        Ok(len)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        if !entries.contains_key(from) {
+            return Err(not_found(from));
+        }
+        check_parent(&entries, to)?;
+
+        // A folder takes everything inside it along
+        let moved: Vec<PathBuf> = entries
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            if let (Some(entry), Ok(relative)) = (entries.remove(&path), path.strip_prefix(from)) {
+                entries.insert(to.join(relative), entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        for folder in path
+            .ancestors()
+            .filter(|folder| !folder.as_os_str().is_empty())
+        {
+            match entries.get(folder) {
+                Some(MemoryEntry::File(_)) => {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{} is a file", folder.display()),
+                    ));
+                }
+                Some(MemoryEntry::Dir) => {}
+                None => {
+                    entries.insert(folder.to_path_buf(), MemoryEntry::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        match entries.get(path) {
+            Some(MemoryEntry::File(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(MemoryEntry::Dir) => Err(Error::other(format!("{} is a folder", path.display()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        match entries.get(path) {
+            Some(MemoryEntry::Dir) => {
+                if entries.keys().any(|entry| entry.parent() == Some(path)) {
+                    return Err(Error::new(
+                        ErrorKind::DirectoryNotEmpty,
+                        format!("{} is not empty", path.display()),
+                    ));
+                }
+                entries.remove(path);
+                Ok(())
+            }
+            Some(MemoryEntry::File(_)) => {
+                Err(Error::other(format!("{} is a file", path.display())))
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        match entries.get(path) {
+            Some(MemoryEntry::Dir) => {
+                entries.retain(|entry, _| !entry.starts_with(path));
+                Ok(())
+            }
+            Some(MemoryEntry::File(_)) => {
+                Err(Error::other(format!("{} is a file", path.display())))
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        if matches!(entries.get(path), Some(MemoryEntry::Dir)) {
+            return Err(Error::other(format!("{} is a folder", path.display())));
+        }
+        check_parent(&entries, path)?;
+        entries.insert(path.to_path_buf(), MemoryEntry::File(contents.to_vec()));
+        Ok(())
+    }
+
+    // Contents aren't shared, the link is a copy that happens to be made the same way
+    fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+        let mut entries = self.lock();
+        let Some(MemoryEntry::File(contents)) = entries.get(original) else {
+            return Err(not_found(original));
+        };
+        let contents = contents.clone();
+        if entries.contains_key(link) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", link.display()),
+            ));
+        }
+        check_parent(&entries, link)?;
+        entries.insert(link.to_path_buf(), MemoryEntry::File(contents));
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let entries = self.lock();
+        match entries.get(path) {
+            Some(MemoryEntry::Dir) => Ok(entries
+                .keys()
+                .filter(|child| child.parent() == Some(path))
+                .cloned()
+                .collect()),
+            Some(MemoryEntry::File(_)) => {
+                Err(Error::other(format!("{} is a file", path.display())))
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata> {
+        match self.lock().get(path) {
+            Some(MemoryEntry::File(contents)) => Ok(FileMetadata {
+                is_dir: false,
+                len: contents.len() as u64,
+                modified: None,
+            }),
+            Some(MemoryEntry::Dir) => Ok(FileMetadata {
+                is_dir: true,
+                len: 0,
+                modified: None,
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn check_parent(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent)
+            if !parent.as_os_str().is_empty()
+                && !matches!(entries.get(parent), Some(MemoryEntry::Dir)) =>
+        {
+            Err(not_found(parent))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
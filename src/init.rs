@@ -0,0 +1,122 @@
+use crate::config::{self, Config};
+use anyhow::bail;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+
+const WATCHER_TYPES: [&str; 3] = ["Copy", "Sync", "Sort"];
+
+// Answers given as flags, anything left out is asked for on a terminal
+pub(crate) struct Answers {
+    pub(crate) name: Option<String>,
+    pub(crate) source: Option<PathBuf>,
+    pub(crate) destination: Option<PathBuf>,
+    pub(crate) watcher_type: Option<String>,
+    pub(crate) place_in_sub: Option<bool>,
+}
+
+// Writes a config with a single path, after checking it loads like any other config would
+pub(crate) async fn run(
+    config_path: Option<PathBuf>,
+    answers: Answers,
+    force: bool,
+) -> Result<(), anyhow::Error> {
+    let config_path = config_path.unwrap_or_else(config::default_config_path);
+    if config_path.exists() && !force {
+        bail!(
+            "{} already exists, pass --force to replace it",
+            config_path.display()
+        );
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    let name = match answers.name {
+        Some(name) => name,
+        None if interactive => ask("Name of the path", Some("anime"))?,
+        None => "anime".to_string(),
+    };
+    let source = match answers.source {
+        Some(source) => source.to_string_lossy().into_owned(),
+        None if interactive => ask("Folder to watch (source)", None)?,
+        None => bail!("--source is required when not running in a terminal"),
+    };
+    let destination = match answers.destination {
+        Some(destination) => destination.to_string_lossy().into_owned(),
+        None if interactive => ask("Library folder (destination)", None)?,
+        None => bail!("--destination is required when not running in a terminal"),
+    };
+    let watcher_type = match answers.watcher_type {
+        Some(watcher_type) => watcher_type,
+        None if interactive => ask("Watcher type, Copy, Sync or Sort", Some("Copy"))?,
+        None => "Copy".to_string(),
+    };
+    // The spelling the config needs, whatever the case of the answer
+    let Some(watcher_type) = WATCHER_TYPES
+        .into_iter()
+        .find(|known| known.eq_ignore_ascii_case(watcher_type.trim()))
+    else {
+        bail!("{watcher_type} is not a watcher type, expected Copy, Sync or Sort");
+    };
+    let place_in_sub = match answers.place_in_sub {
+        Some(place_in_sub) => place_in_sub,
+        None if interactive && watcher_type != "Sync" => {
+            let answer = ask("Put files into a folder per title? (y/n)", Some("y"))?;
+            answer.to_lowercase().starts_with('y')
+        }
+        None => watcher_type != "Sync",
+    };
+
+    let mut path = toml::Table::new();
+    path.insert("name".to_string(), name.into());
+    path.insert("source".to_string(), source.into());
+    path.insert("destination".to_string(), destination.into());
+    path.insert("watcher_type".to_string(), watcher_type.into());
+    path.insert("place_in_sub".to_string(), place_in_sub.into());
+    let mut table = toml::Table::new();
+    table.insert(
+        "paths".to_string(),
+        toml::Value::Array(vec![toml::Value::Table(path)]),
+    );
+    let contents = toml::to_string_pretty(&table)?;
+    let config = Config::from_toml(&contents, &config_path)?;
+    // Watchers create a missing source, which for a typo would be watched forever without a file
+    let written = &config.paths()[0];
+    if !written.source().exists() {
+        bail!(
+            "The source {} does not exist, check the spelling or create it first",
+            written.source().display()
+        );
+    }
+    if !written.has_remote_destination() && !written.destination().exists() {
+        eprintln!(
+            "Warning: the destination {} does not exist yet and will be created",
+            written.destination().display()
+        );
+    }
+
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&config_path, contents)?;
+    println!("Wrote {}", config_path.display());
+    Ok(())
+}
+
+fn ask(question: &str, default: Option<&str>) -> Result<String, anyhow::Error> {
+    loop {
+        match default {
+            Some(default) => print!("{question} [{default}]: "),
+            None => print!("{question}: "),
+        }
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            bail!("No answer to {question:?}");
+        }
+        match (answer.trim(), default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+pub mod cli;
+pub mod commands;
+pub mod config;
+#[cfg(unix)]
+mod control;
+mod copy;
+mod copy_watcher;
+mod dedup;
+mod deletion;
+mod error;
+pub mod filesystem;
+mod init;
+mod merge;
+pub mod metadata;
+mod mount;
+mod organizer;
+mod path_limit;
+mod permissions;
+mod provenance;
+mod prune;
+mod remote;
+mod resolver;
+mod rules;
+mod self_test;
+mod sort_watcher;
+mod state;
+pub mod status;
+mod sync_watcher;
+pub mod template;
+mod transcode;
+pub mod tui;
+mod unparsed;
+mod watch;
+
+pub use crate::config::{Config, PathConfig};
+pub use crate::copy::{CopyOptions, Progress, copy_file};
+pub use crate::copy_watcher::CopyWatcher;
+pub use crate::error::OrganizerError;
+pub use crate::filesystem::{FileSystem, MemoryFileSystem, RealFileSystem};
+pub use crate::metadata::Metadata;
+pub use crate::organizer::Organizer;
+pub use crate::sort_watcher::SortWatcher;
+pub use crate::sync_watcher::SyncWatcher;
+
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+lazy_static! {
+    static ref DETECTED_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+lazy_static! {
+    static ref MOVED_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+// Destinations a mover is writing to right now, across every path, so two sources that map to the
+// same file don't both see it as free
+lazy_static! {
+    static ref RESERVED_DESTINATIONS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+// Files whose mover hasn't returned yet (by how many movers), what shutdown waits for. Finished
+// copies stay in DETECTED_FILES so their later events aren't copied again, so it can't tell.
+// Released from Drop, hence the std Mutex
+lazy_static! {
+    static ref ACTIVE_MOVES: std::sync::Mutex<HashMap<PathBuf, usize>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+fn active_moves() -> std::sync::MutexGuard<'static, HashMap<PathBuf, usize>> {
+    ACTIVE_MOVES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -1,30 +1,13 @@
-mod copy_watcher;
-mod sync_watcher;
-
-use crate::copy_watcher::CopyWatcher;
-use crate::sync_watcher::SyncWatcher;
-use lazy_static::lazy_static;
-use serde::Deserialize;
-use std::collections::HashSet;
-use std::path::PathBuf;
-use tokio::sync::Mutex;
-use tokio::task::JoinSet;
-
-#[derive(Deserialize)]
-struct Config {
-    paths: Vec<PathConfig>,
-}
-
-#[derive(Deserialize)]
-struct PathConfig {
-    source: PathBuf,
-    destination: PathBuf,
-    place_in_sub: bool,
-    name: String,
-    watcher_type: WatcherTypeConfig,
-}
-
-fn setup_logging() {
+use anime_organizer_rs::cli::{Cli, Command};
+use anime_organizer_rs::config::{LogConfig, LogRotation, StartupConfig};
+use anime_organizer_rs::{Config, Organizer, commands, config, tui};
+use clap::Parser;
+use std::io::IsTerminal;
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+
+// The returned guard flushes the log file when dropped, so it has to live until exit
+fn setup_logging(stdout: bool, log_file: Option<&LogConfig>) -> Option<WorkerGuard> {
     use tracing_subscriber::fmt::format::FmtSpan;
     use tracing_subscriber::fmt::time::UtcTime;
     use tracing_subscriber::fmt::{format, layer};
@@ -32,9 +15,22 @@ fn setup_logging() {
     use tracing_subscriber::util::SubscriberInitExt;
     use tracing_subscriber::{EnvFilter, Registry};
 
+    let appender = log_file.and_then(|log| match file_appender(log) {
+        Ok(appender) => Some(appender),
+        // Logging isn't up yet, so this is the only place it can go
+        Err(e) => {
+            eprintln!(
+                "Could not log to {}, continuing without the log file: {e}",
+                log.directory.display()
+            );
+            None
+        }
+    });
+    let (writer, guard) = appender.map(tracing_appender::non_blocking).unzip();
+
     Registry::default()
         .with(EnvFilter::from_default_env())
-        .with(
+        .with(stdout.then(|| {
             layer()
                 .event_format(format().with_timer(UtcTime::rfc_3339()))
                 .with_target(true)
@@ -43,69 +39,108 @@ fn setup_logging() {
                 .with_span_events(FmtSpan::CLOSE)
                 .with_level(true)
                 .with_thread_ids(true)
-                .with_thread_names(true),
-        )
+                .with_thread_names(true)
+        }))
+        .with(writer.map(|writer| {
+            layer()
+                .event_format(format().with_timer(UtcTime::rfc_3339()))
+                .with_ansi(false)
+                .with_target(true)
+                .with_level(true)
+                .with_writer(writer)
+        }))
         .init();
-}
 
-lazy_static! {
-    static ref DETECTED_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+    guard
 }
 
-lazy_static! {
-    static ref MOVED_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+fn file_appender(
+    log: &LogConfig,
+) -> Result<tracing_appender::rolling::RollingFileAppender, anyhow::Error> {
+    use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+    let rotation = match log.rotation {
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&log.file_name);
+    if let Some(max_files) = log.max_files.filter(|max_files| *max_files > 0) {
+        builder = builder.max_log_files(max_files);
+    }
+    Ok(builder.build(&log.directory)?)
 }
 
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
-    setup_logging();
-
-    let config_file = tokio::fs::read_to_string("./paths.toml").await?;
-    let config: Config = toml::from_str(&config_file)?;
-
-    let mut join_set = JoinSet::new();
-    for path_config in config.paths {
-        join_set.spawn(async move {
-            let watcher: FileWatcherType = match &path_config.watcher_type {
-                WatcherTypeConfig::Sync => FileWatcherType::Sync(SyncWatcher::new(path_config)),
-                WatcherTypeConfig::Copy => FileWatcherType::Copy(CopyWatcher::new(path_config)),
-            };
-            watcher.start().await.expect("TODO: panic message");
-        });
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let startup = config::find_config_path(cli.config.clone())
+        .map(|path| StartupConfig::load(&path))
+        .unwrap_or_default();
+    let runtime_config = &startup.runtime;
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(worker_threads) = runtime_config.worker_threads.filter(|threads| *threads > 0) {
+        runtime.worker_threads(worker_threads);
     }
-
-    join_set.join_all().await;
-    Ok(())
+    if let Some(max_blocking_threads) = runtime_config
+        .max_blocking_threads
+        .filter(|threads| *threads > 0)
+    {
+        runtime.max_blocking_threads(max_blocking_threads);
+    }
+    let runtime = runtime.build()?;
+    let result = runtime.block_on(run(cli, startup.log));
+    // Copies abandoned at shutdown may still hold blocking threads, which must not keep the
+    // process alive
+    runtime.shutdown_background();
+    result
 }
 
-trait FileWatcher {
-    async fn start(&self) -> Result<(), anyhow::Error>;
-}
+async fn run(cli: Cli, log_file: Option<LogConfig>) -> Result<(), anyhow::Error> {
+    if let Some(command) = cli.command {
+        // Organizing, merging and watching events explain what they did in the log only
+        let _guard = matches!(
+            command,
+            Command::Reprocess { .. }
+                | Command::MergeDuplicates { .. }
+                | Command::WatchEvents { .. }
+        )
+        .then(|| setup_logging(true, log_file.as_ref()));
+        return commands::run(command, cli.config).await;
+    }
 
-enum FileWatcherType {
-    Sync(SyncWatcher),
-    Copy(CopyWatcher),
-}
+    // The status view owns the terminal, so log lines would only garble it, the log file is fine
+    let tui = cli.tui && std::io::stdout().is_terminal();
+    let _guard = setup_logging(!tui, log_file.as_ref());
+    if cli.tui && !tui {
+        warn!("stdout is not a terminal, logging instead of showing the status view");
+    }
 
-impl FileWatcher for FileWatcherType {
-    async fn start(&self) -> Result<(), anyhow::Error> {
-        match self {
-            FileWatcherType::Sync(sync_watcher) => sync_watcher.start().await,
-            FileWatcherType::Copy(copy_watcher) => copy_watcher.start().await,
+    // An explicit --config is always used, so only a failed search falls back to the environment
+    let organizer = match config::find_config_path(cli.config) {
+        Ok(config_path) => {
+            info!("Using config file {}", config_path.display());
+            Organizer::from_config(&config_path).await?
+        }
+        Err(error) => match Config::from_env()? {
+            Some(config) => {
+                info!("No config file found, using the AORG_* environment variables");
+                Organizer::new(config)
+            }
+            None => return Err(error),
+        },
+    };
+
+    if tui {
+        tokio::select! {
+            result = organizer.run() => result?,
+            result = tui::run() => result?,
         }
+    } else {
+        organizer.run().await?;
     }
+    Ok(())
 }
-
-#[derive(Debug, Deserialize)]
-enum WatcherTypeConfig {
-    // Adds files from source to dest, removes files not present in source from dest, then watches source for further changes
-    Sync,
-    // Watches source and copies files to dest
-    Copy,
-}
-
-async fn copy_file(source: PathBuf, destination: PathBuf) -> std::io::Result<u64> {
-    tokio::task::spawn_blocking(move || std::fs::copy(source, destination)).await?
-}
-
-// TODO: Remove unwraps, add proper error enums
\ No newline at end of file
@@ -0,0 +1,137 @@
+use crate::config::{ConflictPolicy, PathConfig, WatcherTypeConfig};
+use crate::copy_watcher::free_path;
+use crate::filesystem::RealFileSystem;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+// Title folders in the path's destination roots whose names only differ in case or whitespace,
+// e.g. `Attack on Titan` and `attack  on titan`, are merged into the one holding the most entries.
+// Files that exist in both are resolved with the path's on_conflict. Returns the folders merged
+pub fn merge_duplicates(config: &PathConfig, dry_run: bool) -> usize {
+    if matches!(config.watcher_type, WatcherTypeConfig::Sync) {
+        return 0;
+    }
+
+    // Buckets are left alone, merging there would mean copying every object
+    let destination = (!config.has_remote_destination()).then_some(config.destination.as_path());
+    let mut roots: Vec<&Path> = destination
+        .into_iter()
+        .chain(
+            config
+                .destination_rules
+                .iter()
+                .map(|rule| rule.destination.as_path()),
+        )
+        .chain(config.age_destinations())
+        .collect();
+    roots.sort();
+    roots.dedup();
+
+    roots
+        .into_iter()
+        .map(|root| merge_in(config, root, dry_run))
+        .sum()
+}
+
+fn merge_in(config: &PathConfig, root: &Path, dry_run: bool) -> usize {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Could not read {}: {}", root.display(), e);
+            return 0;
+        }
+    };
+
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if !path.is_dir() || config.is_ignored(&path) {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        groups.entry(normalize(&name)).or_default().push(path);
+    }
+
+    let mut merged = 0;
+    for mut folders in groups.into_values().filter(|folders| folders.len() > 1) {
+        folders.sort_by_key(|folder| {
+            let entries = std::fs::read_dir(folder).map_or(0, |entries| entries.count());
+            (std::cmp::Reverse(entries), folder.clone())
+        });
+        let (canonical, duplicates) = folders.split_first().unwrap();
+        for duplicate in duplicates {
+            info!(
+                "{}Merging {} into {}",
+                if dry_run { "Dry run: " } else { "" },
+                duplicate.display(),
+                canonical.display()
+            );
+            if !dry_run {
+                merge_folder(config, duplicate, canonical);
+            }
+            merged += 1;
+        }
+    }
+
+    merged
+}
+
+fn merge_folder(config: &PathConfig, from: &Path, into: &Path) {
+    let entries = match std::fs::read_dir(from) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Could not read {}: {}", from.display(), e);
+            return;
+        }
+    };
+
+    for source in entries.flatten().map(|entry| entry.path()) {
+        let Some(file_name) = source.file_name() else {
+            continue;
+        };
+        let mut destination = into.join(file_name);
+        if source.is_dir() && destination.is_dir() {
+            merge_folder(config, &source, &destination);
+            continue;
+        }
+
+        if destination.exists() {
+            match config.on_conflict() {
+                ConflictPolicy::Overwrite if !destination.is_dir() => {}
+                ConflictPolicy::Skip | ConflictPolicy::Overwrite => {
+                    info!(
+                        "{} already exists, leaving {}",
+                        destination.display(),
+                        source.display()
+                    );
+                    continue;
+                }
+                ConflictPolicy::Rename => {
+                    destination = free_path(&RealFileSystem, &destination, &HashSet::new());
+                }
+            }
+        }
+
+        if let Err(e) = std::fs::rename(&source, &destination) {
+            error!(
+                "Could not move {} to {}: {}",
+                source.display(),
+                destination.display(),
+                e
+            );
+        }
+    }
+
+    // Only goes once everything was moved out, skipped conflicts keep it around
+    if std::fs::remove_dir(from).is_err() {
+        info!("{} was not empty after merging, kept it", from.display());
+    }
+}
+
+// Case and runs of whitespace don't make a different title
+fn normalize(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
@@ -0,0 +1,354 @@
+use crate::config::{
+    AnitomyOptions, CaptureGroup, MetadataElement, ParserKind, RequiredElement, TitleRule,
+};
+use anitomy::ElementKind;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::Path;
+
+lazy_static! {
+    // `2023-10-07`, `2023.10.07` or `2023-10`
+    static ref AIR_DATE: Regex =
+        Regex::new(r"\b((?:19|20)\d{2})[-.](0[1-9]|1[0-2])(?:[-.]\d{2})?\b").unwrap();
+    // `Fall 2023`, `Spring.2024`
+    static ref SEASON_YEAR: Regex =
+        Regex::new(r"(?i)\b(winter|spring|summer|fall|autumn)[ ._-]?((?:19|20)\d{2})\b").unwrap();
+}
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "avi", "m2ts", "m4v", "mkv", "mov", "mp4", "ts", "webm", "wmv",
+];
+
+// Elements parsed out of a release name that the organizer knows how to use
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    // A single episode such as `01` or `12.5`, or for batches the range `01-12`
+    pub episode: Option<String>,
+    pub season: Option<String>,
+    // Fansub or release group, `Group` for `[Group] Show - 01.mkv`
+    pub group: Option<String>,
+    // Video resolution such as `1080p`
+    pub resolution: Option<String>,
+    // Release version, `2` for `v2`
+    pub version: Option<String>,
+    // Year the show aired, e.g. `2019`
+    pub year: Option<String>,
+    // Winter, Spring, Summer or Fall, from a season word or an air date in the name
+    pub broadcast_season: Option<String>,
+    // Release types such as `NCOP`, `OVA` or `Special`
+    pub types: Vec<String>,
+    // Language and audio terms such as `English` or `Dual Audio`
+    pub languages: Vec<String>,
+    // Subtitle terms such as `Softsubs` or `Hardsubs`
+    pub subtitles: Vec<String>,
+    // The CRC32 release names carry in brackets, `ABCD1234` for `[ABCD1234]`
+    pub checksum: Option<String>,
+}
+
+// Extracts metadata from a file name. Paths pick one with `parser`, title rules are always tried
+// before it
+pub trait MetadataParser {
+    fn parse(&self, file_name: &str) -> Metadata;
+}
+
+// Release names as fansub groups write them, `[Group] Show - 01 [1080p].mkv`
+pub struct AnitomyParser(pub AnitomyOptions);
+
+impl MetadataParser for AnitomyParser {
+    fn parse(&self, file_name: &str) -> Metadata {
+        let mut metadata = Metadata::default();
+        let mut episodes = Vec::new();
+
+        for element in anitomy::parse_with_options(file_name, self.0.into()).iter() {
+            match element.kind() {
+                ElementKind::Type => {
+                    metadata.types.push(element.value().to_string());
+                    continue;
+                }
+                ElementKind::Language | ElementKind::AudioTerm => {
+                    metadata.languages.push(element.value().to_string());
+                    continue;
+                }
+                ElementKind::Subtitles => {
+                    metadata.subtitles.push(element.value().to_string());
+                    continue;
+                }
+                // Batches name their first and last episode, `Show 01-12`
+                ElementKind::Episode => {
+                    episodes.push(element.value().to_string());
+                    continue;
+                }
+                _ => {}
+            }
+
+            let slot = match element.kind() {
+                ElementKind::Title => &mut metadata.title,
+                ElementKind::Season => &mut metadata.season,
+                ElementKind::VideoResolution => &mut metadata.resolution,
+                ElementKind::ReleaseVersion => &mut metadata.version,
+                ElementKind::ReleaseGroup => &mut metadata.group,
+                ElementKind::Year => &mut metadata.year,
+                ElementKind::FileChecksum => &mut metadata.checksum,
+                _ => continue,
+            };
+
+            if slot.is_none() {
+                *slot = Some(element.value().to_string());
+            }
+        }
+
+        // A range can also come back as a single `01~12` value, or with the `E` of `E03`
+        let episodes: Vec<&str> = episodes
+            .iter()
+            .flat_map(|episode| episode.split(['-', '~']))
+            .map(|episode| episode.trim().trim_start_matches(['E', 'e']))
+            .filter(|episode| !episode.is_empty())
+            .collect();
+        metadata.episode = match episodes.as_slice() {
+            [] => None,
+            [episode] => Some(episode.to_string()),
+            [first, .., last] => Some(format!("{first}-{last}")),
+        };
+        metadata
+    }
+}
+
+impl From<AnitomyOptions> for anitomy::Options {
+    fn from(options: AnitomyOptions) -> Self {
+        let mut anitomy = anitomy::Options::default();
+        if let Some(episode_title) = options.episode_title {
+            anitomy = anitomy.episode_title(episode_title);
+        }
+        if let Some(release_group) = options.release_group {
+            anitomy = anitomy.release_group(release_group);
+        }
+        if let Some(season) = options.season {
+            anitomy = anitomy.season(season);
+        }
+        if let Some(year) = options.year {
+            anitomy = anitomy.year(year);
+        }
+        if let Some(video_resolution) = options.video_resolution {
+            anitomy = anitomy.video_resolution(video_resolution);
+        }
+        if let Some(file_extension) = options.file_extension {
+            anitomy = anitomy.file_extension(file_extension);
+        }
+        if let Some(file_checksum) = options.file_checksum {
+            anitomy = anitomy.file_checksum(file_checksum);
+        }
+        anitomy
+    }
+}
+
+// The name without its extension is the title, for media that isn't named like a release
+pub struct PassthroughParser;
+
+impl MetadataParser for PassthroughParser {
+    fn parse(&self, file_name: &str) -> Metadata {
+        let stem = Path::new(file_name).file_stem().unwrap_or_default();
+        Metadata {
+            title: Some(stem.to_string_lossy().trim().to_string())
+                .filter(|title| !title.is_empty()),
+            ..Metadata::default()
+        }
+    }
+}
+
+// Only the title rules, a name none of them match has no metadata
+pub struct NoParser;
+
+impl MetadataParser for NoParser {
+    fn parse(&self, _file_name: &str) -> Metadata {
+        Metadata::default()
+    }
+}
+
+impl Metadata {
+    // Custom rules win over the path's parser, which is only consulted when none of them match
+    pub fn parse(
+        file: &Path,
+        rules: &[TitleRule],
+        parser: ParserKind,
+        anitomy: AnitomyOptions,
+    ) -> Self {
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+        let mut metadata = rules
+            .iter()
+            .find_map(|rule| Self::from_rule(rule, &file_name))
+            .unwrap_or_else(|| {
+                let parser: &dyn MetadataParser = match parser {
+                    ParserKind::Anitomy => &AnitomyParser(anitomy),
+                    ParserKind::Passthrough => &PassthroughParser,
+                    ParserKind::Regex => &NoParser,
+                };
+                parser.parse(&file_name)
+            });
+        metadata.read_broadcast_season(&file_name);
+        metadata
+    }
+
+    // A season with its year (`Fall 2023`) wins over an air date (`2023-10-07`), whose month is
+    // mapped to the season it starts in. Either gives the year when the parser found none, a
+    // season without a year is dropped
+    fn read_broadcast_season(&mut self, file_name: &str) {
+        if let Some(captures) = SEASON_YEAR.captures(file_name) {
+            let season = match captures[1].to_lowercase().as_str() {
+                "winter" => "Winter",
+                "spring" => "Spring",
+                "summer" => "Summer",
+                _ => "Fall",
+            };
+            self.broadcast_season = Some(season.to_string());
+            self.year.get_or_insert_with(|| captures[2].to_string());
+        } else if let Some(captures) = AIR_DATE.captures(file_name) {
+            let season = match captures[2].parse::<u32>().unwrap_or_default() {
+                1..=3 => "Winter",
+                4..=6 => "Spring",
+                7..=9 => "Summer",
+                _ => "Fall",
+            };
+            self.broadcast_season = Some(season.to_string());
+            self.year.get_or_insert_with(|| captures[1].to_string());
+        }
+    }
+
+    fn from_rule(rule: &TitleRule, file_name: &str) -> Option<Self> {
+        let captures = rule.pattern.0.captures(file_name)?;
+        let group = |group: Option<&CaptureGroup>| {
+            group
+                .and_then(|group| group.get(&captures))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        Some(Self {
+            title: group(Some(&rule.title_group)),
+            episode: group(rule.episode_group.as_ref()),
+            season: group(rule.season_group.as_ref()),
+            group: None,
+            resolution: None,
+            version: None,
+            year: None,
+            broadcast_season: None,
+            types: Vec::new(),
+            languages: Vec::new(),
+            subtitles: Vec::new(),
+            checksum: None,
+        })
+    }
+
+    pub fn has(&self, element: MetadataElement) -> bool {
+        match element {
+            MetadataElement::Title => self.title.is_some(),
+            MetadataElement::Episode => self.episode.is_some(),
+            MetadataElement::Season => self.season.is_some(),
+            MetadataElement::Type => !self.types.is_empty(),
+            MetadataElement::Resolution => self.resolution.is_some(),
+            MetadataElement::Version => self.version.is_some(),
+            MetadataElement::Language => !self.languages.is_empty(),
+        }
+    }
+
+    pub fn has_all(&self, required: &[RequiredElement]) -> bool {
+        required.iter().all(|required| match required {
+            RequiredElement::One(element) => self.has(*element),
+            RequiredElement::AnyOf(elements) => elements.iter().any(|element| self.has(*element)),
+        })
+    }
+
+    // Releases without a version are the first one
+    pub fn version_number(&self) -> u32 {
+        self.version
+            .as_deref()
+            .and_then(|version| version.trim_start_matches(['v', 'V']).parse().ok())
+            .unwrap_or(1)
+    }
+
+    // A batch release spanning several episodes, whose episode is a range like `01-12`
+    pub fn is_batch(&self) -> bool {
+        self.episode.as_deref().is_some_and(|episode| {
+            episode
+                .split_once('-')
+                .is_some_and(|(first, last)| !first.trim().is_empty() && !last.trim().is_empty())
+        })
+    }
+
+    // Whether both name the same episode, which needs a title and an episode
+    pub fn same_episode(&self, other: &Metadata) -> bool {
+        self.title.is_some()
+            && self.episode.is_some()
+            && self.title == other.title
+            && self.season == other.season
+            && self.episode == other.episode
+    }
+
+    pub(crate) fn value(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "title" => self.title.as_deref(),
+            "episode" => self.episode.as_deref(),
+            "season" => self.season.as_deref(),
+            "group" => self.group.as_deref(),
+            "resolution" => self.resolution.as_deref(),
+            "version" => self.version.as_deref(),
+            "year" => self.year.as_deref(),
+            "broadcast_season" => self.broadcast_season.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            VIDEO_EXTENSIONS
+                .iter()
+                .any(|video| video.eq_ignore_ascii_case(extension))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(file_name: &str) -> Metadata {
+        Metadata::parse(
+            Path::new(file_name),
+            &[],
+            ParserKind::Anitomy,
+            AnitomyOptions::default(),
+        )
+    }
+
+    #[test]
+    fn batch_ranges_become_one_episode_range() {
+        for file_name in [
+            "[Group] Show - 01-12 [1080p].mkv",
+            "[Group] Show - 01~12 [1080p].mkv",
+        ] {
+            let metadata = parse(file_name);
+            assert_eq!(metadata.episode.as_deref(), Some("01-12"), "{file_name}");
+            assert!(metadata.is_batch(), "{file_name}");
+        }
+
+        let metadata = parse("[Group] Show S01E01-E03 [1080p].mkv");
+        assert_eq!(metadata.episode.as_deref(), Some("01-03"));
+        assert!(metadata.is_batch());
+    }
+
+    #[test]
+    fn single_episodes_are_not_batches() {
+        let metadata = parse("[Group] Show - 05 [1080p].mkv");
+        assert_eq!(metadata.episode.as_deref(), Some("05"));
+        assert!(!metadata.is_batch());
+
+        let half = Metadata {
+            episode: Some("12.5".to_string()),
+            ..Metadata::default()
+        };
+        assert!(!half.is_batch());
+        assert!(!Metadata::default().is_batch());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::PathConfig;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const MOUNT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const SOURCE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// A mount point sits on a different device than the folder holding it. An unmounted share is just
+// an empty folder on the parent's device, which a sync would take as "everything was deleted"
+#[cfg(unix)]
+pub fn is_mounted(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    match path.parent().map(Path::metadata) {
+        Some(Ok(parent)) => parent.dev() != metadata.dev(),
+        // The filesystem root is always mounted
+        Some(Err(_)) => false,
+        None => true,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn is_mounted(path: &Path) -> bool {
+    path.exists()
+}
+
+// Whether the path's mount_point, if it has one, is mounted
+pub fn source_available(config: &PathConfig) -> bool {
+    config.mount_point.as_deref().is_none_or(is_mounted)
+}
+
+// Waits as long as the mount_point is not mounted, e.g. a network share during boot
+pub async fn wait_until_available(config: &PathConfig) {
+    if source_available(config) {
+        return;
+    }
+
+    if let Some(mount_point) = &config.mount_point {
+        warn!(
+            "{} is not mounted, waiting for it before starting",
+            mount_point.display()
+        );
+    }
+    while !source_available(config) {
+        tokio::time::sleep(MOUNT_POLL_INTERVAL).await;
+    }
+    info!("{} is mounted now", config.name);
+}
+
+// startup_delay, then up to wait_for_source for the source to have any entry
+pub async fn wait_until_settled(config: &PathConfig) {
+    if let Some(delay) = config.startup_delay() {
+        info!("Waiting {delay:?} before the initial sync");
+        tokio::time::sleep(delay).await;
+    }
+
+    let Some(timeout) = config.wait_for_source() else {
+        return;
+    };
+    let has_entries =
+        || std::fs::read_dir(&config.source).is_ok_and(|mut entries| entries.next().is_some());
+    if has_entries() {
+        return;
+    }
+
+    info!(
+        "{} is empty, waiting up to {timeout:?} for it to fill",
+        config.source.display()
+    );
+    let wait = async {
+        while !has_entries() {
+            tokio::time::sleep(SOURCE_POLL_INTERVAL).await;
+        }
+    };
+    if tokio::time::timeout(timeout, wait).await.is_err() {
+        warn!(
+            "{} is still empty after {timeout:?}, syncing anyway",
+            config.source.display()
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use crate::config::{Config, PathConfig, WatcherTypeConfig};
+use crate::copy_watcher::{CopyWatcher, Mover};
+use crate::error::OrganizerError;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::remote::ObjectStoreFileSystem;
+use crate::sort_watcher::SortWatcher;
+use crate::sync_watcher::SyncWatcher;
+use crate::{active_moves, deletion, prune, rules, state, status, unparsed};
+use anyhow::{anyhow, bail};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::{AbortHandle, Id, JoinSet};
+use tracing::{error, info, warn};
+
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Entry point for embedding: either run every configured watcher, or organize single files with
+// the rules of the path whose source they are in
+pub struct Organizer {
+    config: Config,
+    // Where `reload` re-reads the rules from
+    config_path: Option<PathBuf>,
+    // Only used by organize_file, the watchers always work on the real disk
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Organizer {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            config_path: None,
+            fs: Arc::new(RealFileSystem),
+        }
+    }
+
+    pub fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    pub async fn from_config(path: &Path) -> Result<Self, OrganizerError> {
+        let mut organizer = Self::new(Config::load(path).await?);
+        organizer.config_path = Some(path.to_path_buf());
+        Ok(organizer)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Runs until every watcher has stopped, or until Ctrl-C/SIGTERM once moves in flight are done
+    pub async fn run(self) -> Result<(), OrganizerError> {
+        let config = self.config;
+        let shutdown_timeout = config.shutdown_timeout();
+        state::init(config.state_file)
+            .await
+            .map_err(OrganizerError::Config)?;
+        deletion::resume_pending().await;
+
+        #[cfg(unix)]
+        if let Some(config_path) = self.config_path.clone() {
+            tokio::spawn(reload_on_hangup(config_path));
+        }
+
+        if let Some(control_socket) = config.control_socket {
+            #[cfg(unix)]
+            let config_path = self.config_path;
+            #[cfg(unix)]
+            tokio::spawn(async move {
+                if let Err(e) = crate::control::serve(control_socket, config_path).await {
+                    error!("Control socket stopped: {}", e);
+                }
+            });
+            #[cfg(not(unix))]
+            warn!(
+                "control_socket {} is ignored, it is only supported on Unix",
+                control_socket.display()
+            );
+        }
+
+        if let Some(heartbeat) = config.heartbeat() {
+            tokio::spawn(status::heartbeat_periodically(heartbeat));
+        }
+        tokio::spawn(unparsed::report_periodically(config.unparsed_log));
+        let mut prunes: HashMap<String, AbortHandle> = config
+            .paths
+            .iter()
+            .filter_map(|path| Some((path.name.clone(), spawn_prune(path)?)))
+            .collect();
+
+        let watchers = async move {
+            // Before anything starts, so no reload is missed
+            let mut reloads = rules::subscribe_reloads();
+            let mut globbed = HashMap::new();
+            let mut paths = config.paths;
+            paths.sort_by_key(|path| std::cmp::Reverse(path.priority));
+
+            let mut join_set = JoinSet::new();
+            let mut names = HashMap::new();
+            let mut starting = Vec::new();
+            let mut previous_priority = None;
+            for path_config in paths {
+                if previous_priority.is_some_and(|priority| priority != path_config.priority) {
+                    wait_until_watching(&mut join_set, &names, std::mem::take(&mut starting)).await;
+                }
+                previous_priority = Some(path_config.priority);
+                starting.push(path_config.name.clone());
+
+                let name = path_config.name.clone();
+                let prune = prunes.remove(&name);
+                let glob_source = path_config.glob_source.is_some();
+                let watcher = spawn_watcher(&mut join_set, path_config);
+                names.insert(watcher.id(), name.clone());
+                if glob_source {
+                    globbed.insert(name, GlobbedPath { watcher, prune });
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    joined = join_set.join_next() => {
+                        if joined.is_none() {
+                            break;
+                        }
+                    }
+                    reloaded = reloads.recv() => {
+                        // A lagging receiver still gets the latest reloads, which supersede the
+                        // skipped ones
+                        if let Ok(reloaded) = reloaded {
+                            rematch_globs(&reloaded, &mut join_set, &mut globbed).await;
+                        }
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = watchers => {}
+            _ = shutdown_signal() => {
+                // Dropping the watchers stops them, movers already spawned keep going
+                info!("Shutting down, waiting up to {:?} for moves in flight", shutdown_timeout);
+                if tokio::time::timeout(shutdown_timeout, wait_for_movers()).await.is_err() {
+                    let abandoned = active_moves();
+                    for file in abandoned.keys() {
+                        warn!("Abandoning the move of {}", file.display());
+                    }
+                    warn!("Stopped with {} moves unfinished", abandoned.len());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Copies (or for Sort paths, moves) one file into its library folder and returns where it
+    // ended up. Sync paths mirror rather than organize, so they aren't considered
+    pub async fn organize_file(&self, file: &Path) -> Result<PathBuf, anyhow::Error> {
+        let Some(path_config) = self.config.paths.iter().find(|path| {
+            !matches!(path.watcher_type, WatcherTypeConfig::Sync) && file.starts_with(&path.source)
+        }) else {
+            bail!(
+                "{} is not inside the source of a Copy or Sort path",
+                file.display()
+            );
+        };
+
+        self.organize_with(path_config, file).await
+    }
+
+    // Runs one file through the named path's pipeline wherever it is, e.g. to refile a misfiled
+    // library file after a rule change
+    pub async fn organize_file_as(
+        &self,
+        name: &str,
+        file: &Path,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let Some(path_config) = self.config.paths.iter().find(|path| path.name == name) else {
+            bail!("There is no path named {name}");
+        };
+        if matches!(path_config.watcher_type, WatcherTypeConfig::Sync) {
+            bail!("{name} is a Sync path, which mirrors rather than organizes");
+        }
+
+        self.organize_with(path_config, file).await
+    }
+
+    // Where the path called `name` would organize `file` to, from its name alone: the destination
+    // resolver isn't asked and existing title folders aren't fuzzy matched
+    pub fn destination_for(&self, name: &str, file: &Path) -> Result<PathBuf, anyhow::Error> {
+        let Some(path_config) = self.config.paths.iter().find(|path| path.name == name) else {
+            bail!("There is no path named {name}");
+        };
+        if matches!(path_config.watcher_type, WatcherTypeConfig::Sync) {
+            bail!("{name} is a Sync path, which mirrors rather than organizes");
+        }
+
+        let file = if self.fs.exists(file) {
+            std::path::absolute(file)?
+        } else {
+            path_config.source.join(file)
+        };
+        let transfers = Arc::new(Semaphore::new(path_config.max_concurrent()));
+        let mover = Mover::new(
+            Arc::new(path_config.clone()),
+            self.fs.clone(),
+            file,
+            None,
+            transfers,
+        );
+        // Destinations are already absolute (or bucket URLs) once the config is loaded
+        Ok(mover.compute_destination(None))
+    }
+
+    async fn organize_with(
+        &self,
+        path_config: &PathConfig,
+        file: &Path,
+    ) -> Result<PathBuf, anyhow::Error> {
+        if !self.fs.exists(file) {
+            bail!("{} does not exist", file.display());
+        }
+
+        // Bucket destinations always go through the object store
+        let fs: Arc<dyn FileSystem> = if path_config.has_remote_destination() {
+            Arc::new(ObjectStoreFileSystem::new(path_config)?)
+        } else {
+            self.fs.clone()
+        };
+        let transfers = Arc::new(Semaphore::new(path_config.max_concurrent()));
+        let mover = Mover::new(
+            Arc::new(path_config.clone()),
+            fs,
+            file.to_path_buf(),
+            None,
+            transfers,
+        );
+        mover.start().await.ok_or_else(|| {
+            anyhow!(
+                "{} was not organized, the log has the reason",
+                file.display()
+            )
+        })
+    }
+}
+
+// SIGHUP (`systemctl reload`, `kill -HUP`) reloads the rule tables and re-matches glob sources like
+// the control socket's reload
+#[cfg(unix)]
+async fn reload_on_hangup(config_path: PathBuf) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Could not listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        if let Err(e) = crate::rules::reload(&config_path).await {
+            error!("Could not reload the rules: {}", e);
+        }
+    }
+}
+
+// The watcher of a path expanded from a glob source, and its empty folder cleanup if it has one
+struct GlobbedPath {
+    watcher: AbortHandle,
+    prune: Option<AbortHandle>,
+}
+
+impl GlobbedPath {
+    // Movers the watcher already spawned keep going, like on shutdown
+    fn stop(&self) {
+        self.watcher.abort();
+        if let Some(prune) = &self.prune {
+            prune.abort();
+        }
+    }
+}
+
+// Starts watching folders that newly match a glob source, and stops watching those that no longer
+// match. A watcher that stopped on its own (its folder went away) is started again if the folder is
+// back
+async fn rematch_globs(
+    config: &Config,
+    join_set: &mut JoinSet<()>,
+    globbed: &mut HashMap<String, GlobbedPath>,
+) {
+    let matched: HashMap<&str, &PathConfig> = config
+        .paths
+        .iter()
+        .filter(|path| path.glob_source.is_some())
+        .map(|path| (path.name.as_str(), path))
+        .collect();
+
+    let mut unmatched = Vec::new();
+    globbed.retain(|name, path| {
+        let keep = matched.contains_key(name.as_str()) && !path.watcher.is_finished();
+        if !keep {
+            path.stop();
+        }
+        if !matched.contains_key(name.as_str()) {
+            info!("{name} no longer matches its glob source, stopped watching it");
+            unmatched.push(name.clone());
+        }
+        keep
+    });
+    for name in unmatched {
+        status::remove(&name).await;
+    }
+
+    for (name, path_config) in matched {
+        if globbed.contains_key(name) {
+            continue;
+        }
+        info!(
+            "{name} matches its glob source, watching {}",
+            path_config.source.display()
+        );
+        let prune = spawn_prune(path_config);
+        let watcher = spawn_watcher(join_set, path_config.clone());
+        globbed.insert(name.to_string(), GlobbedPath { watcher, prune });
+    }
+}
+
+// Sync destinations mirror their source, empty folders included
+fn spawn_prune(path: &PathConfig) -> Option<AbortHandle> {
+    if path.cleanup_empty_dirs().is_none() || matches!(path.watcher_type, WatcherTypeConfig::Sync) {
+        return None;
+    }
+    Some(tokio::spawn(prune::remove_empty_dirs_periodically(path.clone())).abort_handle())
+}
+
+fn spawn_watcher(join_set: &mut JoinSet<()>, path_config: PathConfig) -> AbortHandle {
+    let path_name = path_config.name.clone();
+    join_set.spawn(async move {
+        let watcher: FileWatcherType = match &path_config.watcher_type {
+            WatcherTypeConfig::Sync => FileWatcherType::Sync(SyncWatcher::new(path_config)),
+            WatcherTypeConfig::Copy if path_config.has_remote_destination() => {
+                match ObjectStoreFileSystem::new(&path_config) {
+                    Ok(fs) => FileWatcherType::Copy(
+                        CopyWatcher::new(path_config).with_file_system(Arc::new(fs)),
+                    ),
+                    Err(e) => {
+                        error!(
+                            "{}: could not connect to {}: {}",
+                            path_config.name,
+                            path_config.destination.display(),
+                            e
+                        );
+                        return;
+                    }
+                }
+            }
+            WatcherTypeConfig::Copy => FileWatcherType::Copy(CopyWatcher::new(path_config)),
+            WatcherTypeConfig::Sort => FileWatcherType::Sort(SortWatcher::new(path_config)),
+        };
+        if let Err(e) = watcher.start().await {
+            error!("{}: {}", path_name, e);
+        }
+    })
+}
+
+// Ctrl-C, or SIGTERM from e.g. `systemctl stop`
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => error!("Could not listen for SIGTERM: {}", e),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Could not listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+// Movers leave ACTIVE_MOVES when they return, however that went
+async fn wait_for_movers() {
+    while !active_moves().is_empty() {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+// Holds back lower-priority paths until every path in `starting` is watching, or has stopped
+async fn wait_until_watching(
+    join_set: &mut JoinSet<()>,
+    names: &HashMap<Id, String>,
+    mut starting: Vec<String>,
+) {
+    loop {
+        let mut still_starting = Vec::new();
+        for name in starting {
+            if !status::is_watching(&name).await {
+                still_starting.push(name);
+            }
+        }
+        starting = still_starting;
+        if starting.is_empty() {
+            return;
+        }
+
+        tokio::select! {
+            Some(result) = join_set.join_next_with_id() => {
+                let id = match result {
+                    Ok((id, _)) => id,
+                    Err(error) => error.id(),
+                };
+                if let Some(name) = names.get(&id) {
+                    starting.retain(|other| other != name);
+                }
+            }
+            _ = tokio::time::sleep(STARTUP_POLL_INTERVAL) => {}
+        }
+    }
+}
+
+trait FileWatcher {
+    async fn start(&self) -> Result<(), OrganizerError>;
+}
+
+enum FileWatcherType {
+    Sync(SyncWatcher),
+    Copy(CopyWatcher),
+    Sort(SortWatcher),
+}
+
+impl FileWatcher for FileWatcherType {
+    async fn start(&self) -> Result<(), OrganizerError> {
+        match self {
+            FileWatcherType::Sync(sync_watcher) => sync_watcher.start().await,
+            FileWatcherType::Copy(copy_watcher) => copy_watcher.start().await,
+            FileWatcherType::Sort(sort_watcher) => sort_watcher.start().await,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+#[cfg(windows)]
+const MAX_PATH_LENGTH: usize = 260;
+#[cfg(not(windows))]
+const MAX_PATH_LENGTH: usize = 4096;
+// ext4, NTFS and most others cap a single name at 255 bytes (or UTF-16 units)
+const MAX_COMPONENT_LENGTH: usize = 255;
+const HASH_LENGTH: usize = 8;
+
+// Shortens the folders and file name below `root` that are too long for the filesystem. The file
+// name is shortened further when the whole path is still over the platform limit
+pub fn fit(root: &Path, path: PathBuf) -> PathBuf {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return path;
+    };
+
+    let mut components: Vec<String> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => components.push(name.to_string()),
+                None => return path,
+            },
+            _ => return path,
+        }
+    }
+
+    let Some(file_name) = components.pop() else {
+        return path;
+    };
+
+    let mut fitted = root.to_path_buf();
+    for folder in &components {
+        fitted.push(shorten(folder, MAX_COMPONENT_LENGTH, false));
+    }
+
+    // +1 for the separator before the file name
+    let available = MAX_PATH_LENGTH.saturating_sub(fitted.as_os_str().len() + 1);
+    let file_name_length = MAX_COMPONENT_LENGTH.min(available);
+    if file_name_length <= HASH_LENGTH + 1 {
+        warn!(
+            "{} is too long for this platform and can't be shortened",
+            path.display()
+        );
+        return path;
+    }
+
+    fitted.push(shorten(&file_name, file_name_length, true));
+    if fitted != path {
+        info!("Shortened {} to {}", path.display(), fitted.display());
+    }
+
+    fitted
+}
+
+// Windows can't create names ending in a dot or space, which titles like `Show. ` would give, so
+// those are trimmed from every folder and file name below `root`
+pub fn trim_trailing_dots(root: &Path, path: PathBuf) -> PathBuf {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return path;
+    };
+
+    let mut trimmed = root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => match name.trim_end_matches(['.', ' ']) {
+                    "" => trimmed.push("_"),
+                    name => trimmed.push(name),
+                },
+                None => trimmed.push(name),
+            },
+            component => trimmed.push(component),
+        }
+    }
+
+    trimmed
+}
+
+// Cuts the name down to `max_length` bytes, ending it with a hash of the full name so distinct
+// long names stay distinct
+fn shorten(name: &str, max_length: usize, keep_extension: bool) -> String {
+    if name.len() <= max_length {
+        return name.to_string();
+    }
+
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if keep_extension && dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let hash = blake3::hash(name.as_bytes()).to_hex();
+    let suffix = format!("~{}{extension}", &hash[..HASH_LENGTH]);
+
+    let mut end = max_length.saturating_sub(suffix.len()).min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{suffix}", stem[..end].trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn trailing_dots_and_spaces_are_trimmed_on_windows() {
+        let root = Path::new(r"C:\library");
+        assert_eq!(
+            trim_trailing_dots(root, root.join("Show. ").join("Show. - 01.mkv")),
+            root.join("Show").join("Show. - 01.mkv")
+        );
+        assert_eq!(
+            trim_trailing_dots(root, root.join(". . .").join("Show.")),
+            root.join("_").join("Show")
+        );
+    }
+
+    // trim_trailing_dots opts into the same for Windows shares mounted elsewhere
+    #[cfg(not(windows))]
+    #[test]
+    fn trailing_dots_and_spaces_are_trimmed_below_the_root() {
+        let root = Path::new("/mnt/library.");
+        assert_eq!(
+            trim_trailing_dots(root, root.join("Show. ").join("Show. - 01.mkv")),
+            root.join("Show").join("Show. - 01.mkv")
+        );
+    }
+}
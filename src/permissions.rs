@@ -0,0 +1,35 @@
+use std::path::Path;
+
+// Applied to copied files and folders, e.g. `0o664` so a media server running as another user
+// can read them
+pub fn apply(path: &Path, mode: Option<u32>) {
+    if let Some(mode) = mode {
+        set_mode(path, mode);
+    }
+}
+
+// Applied to `folder` and every folder between it and `root`, for freshly created trees
+pub fn apply_below(root: &Path, folder: &Path, mode: Option<u32>) {
+    let Some(mode) = mode else {
+        return;
+    };
+
+    for folder in folder.ancestors().take_while(|ancestor| *ancestor != root) {
+        set_mode(folder, mode);
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)) {
+        tracing::error!("Could not set mode {mode:o} on {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) {
+    static WARNING: std::sync::Once = std::sync::Once::new();
+    WARNING.call_once(|| tracing::warn!("file_mode and dir_mode are only supported on Unix"));
+}
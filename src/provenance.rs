@@ -0,0 +1,39 @@
+use std::path::Path;
+
+const SOURCE_ATTRIBUTE: &str = "user.aorg.source";
+const ORGANIZED_AT_ATTRIBUTE: &str = "user.aorg.organized_at";
+
+// Tags an organized file with where it came from and when (Unix seconds), readable with
+// `getfattr -d`. Filesystems without user xattrs only get a warning
+#[cfg(unix)]
+pub fn record(source: &Path, destination: &Path) {
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let organized_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .to_string();
+    let attributes = [
+        (SOURCE_ATTRIBUTE, source.as_os_str().as_bytes()),
+        (ORGANIZED_AT_ATTRIBUTE, organized_at.as_bytes()),
+    ];
+
+    for (name, value) in attributes {
+        if let Err(e) = xattr::set(destination, name, value) {
+            tracing::warn!("Could not set {name} on {}: {}", destination.display(), e);
+            return;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn record(_source: &Path, _destination: &Path) {
+    static WARNING: std::sync::Once = std::sync::Once::new();
+    WARNING.call_once(|| {
+        tracing::warn!(
+            "write_provenance_xattr is only supported on Unix, {SOURCE_ATTRIBUTE} and {ORGANIZED_AT_ATTRIBUTE} are not written"
+        )
+    });
+}
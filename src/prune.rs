@@ -0,0 +1,156 @@
+use crate::config::PathConfig;
+use crate::metadata::{self, Metadata};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info};
+use walkdir::WalkDir;
+
+// Removes episodes of the same title and season as `organized` from its folder, keeping the
+// `keep` highest episode numbers. Files sharing a removed video's stem (subtitles, ...) go too
+pub fn keep_latest(config: &PathConfig, organized: &Path, metadata: &Metadata, keep: usize) {
+    let (Some(folder), Some(_)) = (organized.parent(), &metadata.title) else {
+        return;
+    };
+
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Could not read {} to prune it: {}", folder.display(), e);
+            return;
+        }
+    };
+
+    let files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+
+    // Episodes that don't parse as a number (batches, `01-02`, ...) are never pruned
+    let mut episodes: Vec<(f64, &PathBuf)> = files
+        .iter()
+        .filter(|path| metadata::is_video(path))
+        .filter_map(|path| {
+            let other = Metadata::parse(path, &config.title_rules, config.parser, config.anitomy);
+            if other.title != metadata.title || other.season != metadata.season {
+                return None;
+            }
+
+            let episode = other.episode?.parse::<f64>().ok()?;
+            Some((episode, path))
+        })
+        .collect();
+
+    if episodes.len() <= keep {
+        return;
+    }
+
+    episodes.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, video) in episodes.split_off(keep) {
+        let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{stem}.");
+        files
+            .iter()
+            .filter(|file| {
+                *file == video
+                    || file
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+            })
+            .for_each(|file| remove(config, file));
+    }
+}
+
+fn remove(config: &PathConfig, file: &Path) {
+    if config.dry_run {
+        info!("Dry run: would prune {}", file.display());
+        return;
+    }
+
+    match std::fs::remove_file(file) {
+        Ok(_) => info!("Pruned {}", file.display()),
+        Err(e) => error!("Could not prune {}: {}", file.display(), e),
+    }
+}
+
+// Folders this new may still be about to receive a file from a mover that just created them
+const EMPTY_DIR_MIN_AGE: Duration = Duration::from_secs(10 * 60);
+
+// Every `cleanup_empty_dirs_secs`, removes the empty folders (deepest first, so folders holding
+// only empty folders go too) in the path's destination roots, e.g. `Show/Season 01` shells left
+// by deletions. The roots themselves are kept
+pub async fn remove_empty_dirs_periodically(config: PathConfig) {
+    let Some(period) = config.cleanup_empty_dirs() else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(period);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let config = config.clone();
+        let removed = tokio::task::spawn_blocking(move || remove_empty_dirs(&config))
+            .await
+            .unwrap_or_default();
+        if removed > 0 {
+            info!("Removed {removed} empty folders");
+        }
+    }
+}
+
+fn remove_empty_dirs(config: &PathConfig) -> usize {
+    let mut roots: Vec<&Path> = std::iter::once(config.destination.as_path())
+        .chain(
+            config
+                .destination_rules
+                .iter()
+                .map(|rule| rule.destination.as_path()),
+        )
+        .chain(config.age_destinations())
+        .collect();
+    roots.sort();
+    roots.dedup();
+
+    let mut removed = 0;
+    let mut emptied = HashSet::new();
+    for root in roots {
+        for entry in WalkDir::new(root)
+            .min_depth(1)
+            .contents_first(true)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_dir())
+        {
+            let folder = entry.path();
+            let is_empty =
+                std::fs::read_dir(folder).is_ok_and(|mut entries| entries.next().is_none());
+            // Removing an empty child just updated the folder's modification time
+            let is_recent = !emptied.contains(folder)
+                && entry
+                    .metadata()
+                    .ok()
+                    .and_then(|metadata| metadata.modified().ok())
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_none_or(|age| age < EMPTY_DIR_MIN_AGE);
+            if !is_empty || is_recent {
+                continue;
+            }
+
+            if config.dry_run {
+                info!("Dry run: would remove empty folder {}", folder.display());
+                continue;
+            }
+            match std::fs::remove_dir(folder) {
+                Ok(_) => {
+                    removed += 1;
+                    if let Some(parent) = folder.parent() {
+                        emptied.insert(parent.to_path_buf());
+                    }
+                }
+                Err(e) => error!("Could not remove empty folder {}: {}", folder.display(), e),
+            }
+        }
+    }
+    removed
+}
@@ -0,0 +1,310 @@
+use crate::config::PathConfig;
+use crate::copy::{CopyOptions, Progress};
+use crate::filesystem::{FileMetadata, FileSystem, RealFileSystem};
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, RetryConfig, WriteMultipart};
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+pub(crate) const SCHEME: &str = "s3://";
+// Below this files go up in a single request, above it in parts of this size
+const PART_SIZE: usize = 16 * 1024 * 1024;
+const PARTS_IN_FLIGHT: usize = 4;
+const MAX_RETRIES: usize = 10;
+
+// A Copy path's destination in an S3 compatible bucket, `destination = "s3://bucket/prefix"`.
+// Paths below the destination are object keys, everything else (the source, local
+// destination_rules) is the real disk, so the usual title and folder layout becomes the keys.
+// Region and endpoint (e.g. for Backblaze B2) come from the usual AWS_* variables, credentials too
+// unless the path's `s3` table sets them
+pub(crate) struct ObjectStoreFileSystem {
+    root: PathBuf,
+    prefix: String,
+    store: Box<dyn ObjectStore>,
+    local: RealFileSystem,
+}
+
+impl ObjectStoreFileSystem {
+    pub(crate) fn new(config: &PathConfig) -> Result<Self, anyhow::Error> {
+        let url = config.destination.to_string_lossy();
+        let Some(location) = url.strip_prefix(SCHEME) else {
+            anyhow::bail!("{url} is not an {SCHEME} URL");
+        };
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .with_retry(RetryConfig {
+                max_retries: MAX_RETRIES,
+                ..RetryConfig::default()
+            });
+        let credentials = &config.s3;
+        if let Some(access_key_id) = &credentials.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &credentials.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        if let Some(session_token) = &credentials.session_token {
+            builder = builder.with_token(session_token);
+        }
+        let store = builder.build()?;
+
+        Ok(Self {
+            root: config.destination.clone(),
+            prefix: prefix.trim_matches('/').to_string(),
+            store: Box::new(store),
+            local: RealFileSystem,
+        })
+    }
+
+    // The object key for a path below the destination, None for local paths
+    fn key(&self, path: &Path) -> Option<ObjectPath> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let parts = std::iter::once(self.prefix.clone())
+            .chain(
+                relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+            )
+            .filter(|part| !part.is_empty());
+        Some(ObjectPath::from_iter(parts))
+    }
+
+    async fn upload(
+        &self,
+        source: &Path,
+        key: &ObjectPath,
+        progress: Option<&Progress>,
+    ) -> std::io::Result<u64> {
+        let mut file = tokio::fs::File::open(source).await?;
+        let len = file.metadata().await?.len();
+        if len <= PART_SIZE as u64 {
+            let mut contents = Vec::with_capacity(len as usize);
+            file.read_to_end(&mut contents).await?;
+            self.store.put(key, contents.into()).await.map_err(to_io)?;
+            if let Some(progress) = progress {
+                progress.advance(len);
+            }
+            return Ok(len);
+        }
+
+        let upload = self.store.put_multipart(key).await.map_err(to_io)?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+        let mut buffer = vec![0; PART_SIZE];
+        loop {
+            let read = match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    let _ = writer.abort().await;
+                    return Err(e);
+                }
+            };
+            if let Err(e) = writer.wait_for_capacity(PARTS_IN_FLIGHT).await {
+                let _ = writer.abort().await;
+                return Err(to_io(e));
+            }
+            writer.write(&buffer[..read]);
+            if let Some(progress) = progress {
+                progress.advance(read as u64);
+            }
+        }
+        writer.finish().await.map_err(to_io)?;
+        Ok(len)
+    }
+
+    // Every object at or below the key, object stores have no folders to walk
+    async fn objects_below(&self, key: &ObjectPath) -> std::io::Result<Vec<ObjectPath>> {
+        let mut objects = Vec::new();
+        let mut prefixes = vec![key.clone()];
+        while let Some(prefix) = prefixes.pop() {
+            let listing = self
+                .store
+                .list_with_delimiter(Some(&prefix))
+                .await
+                .map_err(to_io)?;
+            objects.extend(listing.objects.into_iter().map(|object| object.location));
+            prefixes.extend(listing.common_prefixes);
+        }
+        Ok(objects)
+    }
+
+    async fn is_prefix(&self, key: &ObjectPath) -> std::io::Result<bool> {
+        let listing = self
+            .store
+            .list_with_delimiter(Some(key))
+            .await
+            .map_err(to_io)?;
+        Ok(!listing.objects.is_empty() || !listing.common_prefixes.is_empty())
+    }
+}
+
+impl FileSystem for ObjectStoreFileSystem {
+    fn copy(
+        &self,
+        source: &Path,
+        destination: &Path,
+        progress: Option<&Progress>,
+        options: CopyOptions,
+    ) -> std::io::Result<u64> {
+        match (self.key(source), self.key(destination)) {
+            (None, None) => self.local.copy(source, destination, progress, options),
+            (None, Some(to)) => block_on(self.upload(source, &to, progress)),
+            (Some(from), Some(to)) => block_on(async {
+                let len = self.store.head(&from).await.map_err(to_io)?.size;
+                self.store.copy(&from, &to).await.map_err(to_io)?;
+                Ok(len)
+            }),
+            (Some(_), None) => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("downloading {} is not supported", source.display()),
+            )),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let (Some(from_key), Some(to_key)) = (self.key(from), self.key(to)) else {
+            return self.local.rename(from, to);
+        };
+
+        // A folder is every object below it, each copied to its new key and then removed
+        block_on(async {
+            if self.store.head(&from_key).await.is_ok() {
+                self.store.copy(&from_key, &to_key).await.map_err(to_io)?;
+                return self.store.delete(&from_key).await.map_err(to_io);
+            }
+            for object in self.objects_below(&from_key).await? {
+                let Some(relative) = object.prefix_match(&from_key) else {
+                    continue;
+                };
+                let target = ObjectPath::from_iter(to_key.parts().chain(relative));
+                self.store.copy(&object, &target).await.map_err(to_io)?;
+                self.store.delete(&object).await.map_err(to_io)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        match self.key(path) {
+            // Folders exist as soon as an object below them does
+            Some(_) => Ok(()),
+            None => self.local.create_dir_all(path),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        match self.key(path) {
+            Some(key) => block_on(self.store.delete(&key)).map_err(to_io),
+            None => self.local.remove_file(path),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        let Some(key) = self.key(path) else {
+            return self.local.remove_dir_all(path);
+        };
+
+        block_on(async {
+            for object in self.objects_below(&key).await? {
+                self.store.delete(&object).await.map_err(to_io)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn remove_dir(&self, path: &Path) -> std::io::Result<()> {
+        match self.key(path) {
+            // A folder without objects below it is already gone
+            Some(_) => Ok(()),
+            None => self.local.remove_dir(path),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        match self.key(path) {
+            Some(key) => block_on(self.store.put(&key, contents.to_vec().into()))
+                .map(|_| ())
+                .map_err(to_io),
+            None => self.local.write(path, contents),
+        }
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+        match (self.key(original), self.key(link)) {
+            (None, None) => self.local.hard_link(original, link),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                "object stores have no hard links",
+            )),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let Some(key) = self.key(path) else {
+            return self.local.read_dir(path);
+        };
+
+        let listing = block_on(self.store.list_with_delimiter(Some(&key))).map_err(to_io)?;
+        Ok(listing
+            .common_prefixes
+            .iter()
+            .chain(listing.objects.iter().map(|object| &object.location))
+            .filter_map(|child| child.filename())
+            .map(|name| path.join(name))
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata> {
+        let Some(key) = self.key(path) else {
+            return self.local.metadata(path);
+        };
+        if path == self.root {
+            return Ok(FileMetadata {
+                is_dir: true,
+                len: 0,
+                modified: None,
+            });
+        }
+
+        block_on(async {
+            match self.store.head(&key).await {
+                Ok(object) => Ok(FileMetadata {
+                    is_dir: false,
+                    len: object.size,
+                    modified: Some(object.last_modified.into()),
+                }),
+                Err(object_store::Error::NotFound { .. }) => {
+                    if self.is_prefix(&key).await? {
+                        Ok(FileMetadata {
+                            is_dir: true,
+                            len: 0,
+                            modified: None,
+                        })
+                    } else {
+                        Err(Error::new(
+                            ErrorKind::NotFound,
+                            format!("{} does not exist", path.display()),
+                        ))
+                    }
+                }
+                Err(e) => Err(to_io(e)),
+            }
+        })
+    }
+}
+
+// FileSystem is synchronous, movers call it from the runtime as well as from blocking copies
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+fn to_io(error: object_store::Error) -> Error {
+    match error {
+        object_store::Error::NotFound { .. } => Error::new(ErrorKind::NotFound, error),
+        error => Error::other(error),
+    }
+}
@@ -0,0 +1,155 @@
+use crate::config::AnitomyOptions;
+use serde::Serialize;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+#[derive(Serialize)]
+struct Request<'a> {
+    file_name: &'a str,
+    elements: Vec<Element>,
+}
+
+#[derive(Serialize)]
+struct Element {
+    kind: String,
+    value: String,
+}
+
+// Asks `destination_resolver_command` where the file goes: the file name and its anitomy elements
+// are written to stdin as JSON, the first line of stdout is the folder relative to the destination
+// root. None (with a warning) when the command fails, times out or answers with something unusable
+pub(crate) async fn resolve(
+    command: &[String],
+    timeout: Duration,
+    anitomy: AnitomyOptions,
+    file: &Path,
+) -> Option<PathBuf> {
+    let (program, args) = command.split_first()?;
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let request = Request {
+        file_name: &file_name,
+        elements: anitomy::parse_with_options(&file_name, anitomy.into())
+            .iter()
+            .map(|element| Element {
+                kind: format!("{:?}", element.kind()),
+                value: element.value().to_string(),
+            })
+            .collect(),
+    };
+    let request = match serde_json::to_vec(&request) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Could not encode resolver input for {file_name}: {}", e);
+            return None;
+        }
+    };
+
+    let run = async {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        // Written alongside reading stdout, a resolver that answers before reading all of its
+        // input would otherwise block on a full pipe while we block on its stdin
+        let writer = child
+            .stdin
+            .take()
+            .map(|mut stdin| tokio::spawn(async move { stdin.write_all(&request).await }));
+        let output = child.wait_with_output().await;
+        // Not reading the input at all is up to the resolver
+        if let Some(writer) = writer
+            && let Ok(Err(e)) = writer.await
+            && e.kind() != std::io::ErrorKind::BrokenPipe
+        {
+            warn!("Could not write resolver input for {file_name}: {}", e);
+        }
+        output
+    };
+
+    let output = match tokio::time::timeout(timeout, run).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => {
+            warn!(
+                "Destination resolver exited with {} for {file_name}",
+                output.status
+            );
+            return None;
+        }
+        Ok(Err(e)) => {
+            warn!("Could not run destination resolver for {file_name}: {}", e);
+            return None;
+        }
+        Err(_) => {
+            warn!("Destination resolver timed out after {timeout:?} for {file_name}");
+            return None;
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // An empty path would pass the check below and put the file in the destination root
+    let Some(folder) = stdout
+        .lines()
+        .next()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    else {
+        warn!("Destination resolver answered without a folder for {file_name}");
+        return None;
+    };
+    let folder = PathBuf::from(folder);
+    // Anything that could climb out of the destination root is refused
+    if !folder
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        warn!(
+            "Ignoring destination {} from the resolver for {file_name}, it must be a relative path inside the destination",
+            folder.display()
+        );
+        return None;
+    }
+
+    Some(folder)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    async fn resolve_with(script: &str) -> Option<PathBuf> {
+        let command = ["sh", "-c", script].map(ToString::to_string);
+        resolve(
+            &command,
+            Duration::from_secs(10),
+            AnitomyOptions::default(),
+            Path::new("/source/[Group] Show Name - 01 [1080p].mkv"),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn first_line_of_the_answer_is_the_folder() {
+        assert_eq!(
+            resolve_with("cat > /dev/null; printf 'Show Name/Season 1\\nignored\\n'").await,
+            Some(PathBuf::from("Show Name/Season 1"))
+        );
+        // Answering without reading the input is fine too
+        assert_eq!(
+            resolve_with("echo 'Show Name'").await,
+            Some(PathBuf::from("Show Name"))
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_or_escaping_answers_are_ignored() {
+        assert_eq!(resolve_with("cat > /dev/null").await, None);
+        assert_eq!(resolve_with("cat > /dev/null; echo; echo Show").await, None);
+        assert_eq!(resolve_with("cat > /dev/null; echo ../Show").await, None);
+    }
+}
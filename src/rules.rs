@@ -0,0 +1,86 @@
+use crate::config::{Config, DestinationRule, PathConfig, TitleRule};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+// The parts of a path's config that change as new shows air, reloadable without a restart
+struct RuleTables {
+    title_rules: Vec<TitleRule>,
+    destination_rules: Vec<DestinationRule>,
+    allowed_groups: Vec<String>,
+    blocked_groups: Vec<String>,
+}
+
+lazy_static! {
+    // By path name, only for paths reloaded since startup
+    static ref RELOADED: Mutex<HashMap<String, Arc<RuleTables>>> = Mutex::new(HashMap::new());
+    // Every reloaded config, for re-matching glob sources
+    static ref RELOADS: broadcast::Sender<Arc<Config>> = broadcast::channel(4).0;
+}
+
+// Re-reads the rule tables of every path from the config file and has the folders matching glob
+// sources re-matched, leaving everything else as it is. Returns a line per path with the reloaded
+// counts
+pub async fn reload(config_path: &Path) -> Result<String, anyhow::Error> {
+    let config = Config::load(config_path).await?;
+    let mut reloaded = lock();
+    let lines: Vec<String> = config
+        .paths()
+        .iter()
+        .map(|path| {
+            let line = format!(
+                "{}: {} title rules, {} destination rules, {} allowed groups, {} blocked groups",
+                path.name,
+                path.title_rules.len(),
+                path.destination_rules.len(),
+                path.allowed_groups.len(),
+                path.blocked_groups.len()
+            );
+            info!("Reloaded rules of {line}");
+            reloaded.insert(
+                path.name.clone(),
+                Arc::new(RuleTables {
+                    title_rules: path.title_rules.clone(),
+                    destination_rules: path.destination_rules.clone(),
+                    allowed_groups: path.allowed_groups.clone(),
+                    blocked_groups: path.blocked_groups.clone(),
+                }),
+            );
+            line
+        })
+        .collect();
+    drop(reloaded);
+    warn!(
+        "Only rules and the folders matching glob sources were reloaded, other changes to the config need a restart"
+    );
+    // Nobody listening only means the watchers aren't running
+    let _ = RELOADS.send(Arc::new(config));
+    Ok(lines.join("\n"))
+}
+
+pub(crate) fn subscribe_reloads() -> broadcast::Receiver<Arc<Config>> {
+    RELOADS.subscribe()
+}
+
+// The config movers of the path are started with: as loaded, with the last reloaded rules
+pub(crate) fn current(config: &Arc<PathConfig>) -> Arc<PathConfig> {
+    let Some(rules) = lock().get(&config.name).cloned() else {
+        return config.clone();
+    };
+    Arc::new(PathConfig {
+        title_rules: rules.title_rules.clone(),
+        destination_rules: rules.destination_rules.clone(),
+        allowed_groups: rules.allowed_groups.clone(),
+        blocked_groups: rules.blocked_groups.clone(),
+        ..(**config).clone()
+    })
+}
+
+fn lock() -> std::sync::MutexGuard<'static, HashMap<String, Arc<RuleTables>>> {
+    RELOADED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,177 @@
+use crate::sync_watcher::SyncWatcher;
+use crate::{Config, Organizer, watch};
+use anyhow::{anyhow, bail};
+use notify::RecursiveMode;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SAMPLE_NAME: &str = "[SelfTest] Sample Show - 01 [1080p].mkv";
+const SAMPLE_CONTENTS: &[u8] = b"anime-organizer self-test\n";
+// Network mounts can be slow to report, but not this slow
+const WATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// End-to-end smoke test of what the watchers rely on, in a throwaway folder inside `dir` so a
+// deployment can be checked on the disks it will really use. Every step is reported, not only
+// the first failure
+pub(crate) async fn run(dir: Option<PathBuf>) -> Result<(), anyhow::Error> {
+    let root = dir
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("anime-organizer-self-test-{}", std::process::id()));
+    let (source, library, mirror) = (
+        root.join("source"),
+        root.join("library"),
+        root.join("mirror"),
+    );
+    let sample = source.join(SAMPLE_NAME);
+    println!("Testing in {}", root.display());
+
+    let mut failed = 0;
+    let mut report = |capability: &str, result: Result<String, anyhow::Error>| match result {
+        Ok(detail) => println!("PASS {capability}: {detail}"),
+        Err(e) => {
+            failed += 1;
+            println!("FAIL {capability}: {e:#}");
+        }
+    };
+
+    let setup = [&source, &library, &mirror]
+        .into_iter()
+        .try_for_each(std::fs::create_dir_all);
+    if let Err(e) = setup {
+        report(
+            "create",
+            Err(anyhow!("could not create the test folders: {e}")),
+        );
+        bail!("Self-test could not start");
+    }
+
+    // Watching before the sample is written, so its creation is what gets reported
+    let watching = watch::event_channel(16, None, |_| true).and_then(|(mut watcher, rx)| {
+        watcher.watch(&source, RecursiveMode::NonRecursive)?;
+        Ok((watcher, rx))
+    });
+    report(
+        "create",
+        std::fs::write(&sample, SAMPLE_CONTENTS)
+            .map(|_| format!("wrote {}", sample.display()))
+            .map_err(Into::into),
+    );
+    report(
+        "watch",
+        match watching {
+            Ok((_watcher, mut rx)) => watch_event(&mut rx, &sample).await,
+            Err(e) => Err(anyhow!("could not watch {}: {e}", source.display())),
+        },
+    );
+
+    match test_config(&source, &library, &mirror) {
+        Ok(config) => {
+            report("copy", copy(config.clone(), &sample).await);
+            let sync = SyncWatcher::new(config.paths[1].clone());
+            report("sync", sync_copies(&sync, &mirror).await);
+            report("delete", sync_deletes(&sync, &sample, &mirror).await);
+        }
+        Err(e) => report("copy", Err(e)),
+    }
+
+    if let Err(e) = std::fs::remove_dir_all(&root) {
+        println!("Could not remove {}: {e}", root.display());
+    }
+    if failed > 0 {
+        bail!("{failed} self-test steps failed");
+    }
+    println!("All self-test steps passed");
+    Ok(())
+}
+
+// A Copy path and a Sync path sharing the test source
+fn test_config(source: &Path, library: &Path, mirror: &Path) -> Result<Config, anyhow::Error> {
+    let path = |name: &str, watcher_type: &str, destination: &Path| {
+        let mut path = toml::Table::new();
+        path.insert("name".to_string(), name.into());
+        path.insert("watcher_type".to_string(), watcher_type.into());
+        path.insert(
+            "source".to_string(),
+            source.to_string_lossy().into_owned().into(),
+        );
+        path.insert(
+            "destination".to_string(),
+            destination.to_string_lossy().into_owned().into(),
+        );
+        path.insert("place_in_sub".to_string(), true.into());
+        path.insert("wait_time_secs".to_string(), toml::Value::Integer(0));
+        toml::Value::Table(path)
+    };
+
+    let mut table = toml::Table::new();
+    table.insert(
+        "paths".to_string(),
+        toml::Value::Array(vec![
+            path("self-test-copy", "Copy", library),
+            path("self-test-sync", "Sync", mirror),
+        ]),
+    );
+    let config: Config = toml::Value::Table(table).try_into()?;
+    config.validate()?;
+    Ok(config)
+}
+
+async fn watch_event(
+    rx: &mut tokio::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+    sample: &Path,
+) -> Result<String, anyhow::Error> {
+    let received = tokio::time::timeout(WATCH_TIMEOUT, async {
+        while let Some(event) = rx.recv().await {
+            match event {
+                Ok(event) if event.paths.iter().any(|path| path == sample) => {
+                    return Ok(format!("{:?}", event.kind));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(anyhow!("the watcher reported an error: {e}")),
+            }
+        }
+        Err(anyhow!("the watcher stopped"))
+    })
+    .await;
+    received.unwrap_or_else(|_| {
+        Err(anyhow!(
+            "no event within {WATCH_TIMEOUT:?}, this filesystem may need poll_fallback_secs"
+        ))
+    })
+}
+
+async fn copy(config: Config, sample: &Path) -> Result<String, anyhow::Error> {
+    let organizer = Organizer::new(config);
+    let destination = organizer.organize_file_as("self-test-copy", sample).await?;
+    verify(&destination)?;
+    Ok(format!("organized into {}", destination.display()))
+}
+
+async fn sync_copies(sync: &SyncWatcher, mirror: &Path) -> Result<String, anyhow::Error> {
+    sync.sync_dirs().await?;
+    let mirrored = mirror.join(SAMPLE_NAME);
+    verify(&mirrored)?;
+    Ok(format!("mirrored to {}", mirrored.display()))
+}
+
+async fn sync_deletes(
+    sync: &SyncWatcher,
+    sample: &Path,
+    mirror: &Path,
+) -> Result<String, anyhow::Error> {
+    std::fs::remove_file(sample)?;
+    sync.sync_dirs().await?;
+    let mirrored = mirror.join(SAMPLE_NAME);
+    if mirrored.exists() {
+        bail!("{} is still there after syncing", mirrored.display());
+    }
+    Ok("removed the source and its mirrored copy".to_string())
+}
+
+fn verify(path: &Path) -> Result<(), anyhow::Error> {
+    let contents = std::fs::read(path)?;
+    if contents != SAMPLE_CONTENTS {
+        bail!("{} doesn't match the sample", path.display());
+    }
+    Ok(())
+}
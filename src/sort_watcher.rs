@@ -0,0 +1,142 @@
+use crate::DETECTED_FILES;
+use crate::config::PathConfig;
+use crate::copy_watcher::Mover;
+use crate::error::OrganizerError;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::status::{self, WatcherState};
+use crate::watch::Watched;
+use crate::{mount, rules, watch};
+use notify::RecursiveMode;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{Instrument, error, info};
+
+pub struct SortWatcher {
+    config: Arc<PathConfig>,
+    fs: Arc<dyn FileSystem>,
+    transfers: Arc<Semaphore>,
+}
+
+impl SortWatcher {
+    pub fn new(config: PathConfig) -> Self {
+        Self {
+            transfers: Arc::new(Semaphore::new(config.max_concurrent())),
+            config: Arc::new(config),
+            fs: Arc::new(RealFileSystem),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(name = %self.config.name))]
+    pub async fn start(&self) -> Result<(), OrganizerError> {
+        info!(
+            "Starting {} thread. Sorting existing files",
+            self.config.name
+        );
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Syncing
+        })
+        .await;
+
+        mount::wait_until_available(&self.config).await;
+        let mut resumes = status::subscribe_resumes();
+        let existing = self
+            .fs
+            .create_dir_all(&self.config.source)
+            .and_then(|_| self.fs.read_dir(&self.config.source))
+            .map_err(OrganizerError::copy(&self.config.source))?;
+        self.sort_files(existing).await;
+
+        let events = self.config.watched_events();
+        let (mut watcher, mut rx) = watch::event_channel(
+            self.config.channel_capacity(),
+            self.config.poll_fallback(),
+            move |event| watch::classify(&event.kind).is_some_and(|kind| events.contains(&kind)),
+        )
+        .map_err(OrganizerError::watch(&self.config.source))?;
+
+        // Only the root holds unsorted files, anything deeper is already in a title folder
+        watcher
+            .watch(&self.config.source, RecursiveMode::NonRecursive)
+            .map_err(OrganizerError::watch(&self.config.source))?;
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Watching
+        })
+        .await;
+        loop {
+            tokio::select! {
+                event = watch::next_event(
+                    &mut rx,
+                    &mut watcher,
+                    &self.config.source,
+                    RecursiveMode::NonRecursive,
+                ) => {
+                    match event {
+                        Some(Watched::Event(event)) => {
+                            status::record_event(&self.config.name).await;
+                            self.sort_files(event.paths).await;
+                        }
+                        Some(Watched::Dropped(_)) => self.sort_root().await,
+                        None => break,
+                    }
+                }
+                _ = status::resumed(&mut resumes, &self.config.name) => self.sort_root().await,
+            }
+        }
+
+        Ok(())
+    }
+
+    // Everything left in the root is unsorted, including what arrived while events were dropped
+    async fn sort_root(&self) {
+        match self.fs.read_dir(&self.config.source) {
+            Ok(unsorted) => self.sort_files(unsorted).await,
+            Err(e) => error!(
+                "Could not scan {} to catch up: {}",
+                self.config.source.display(),
+                e
+            ),
+        }
+    }
+
+    async fn sort_files(&self, paths: Vec<PathBuf>) {
+        if status::is_paused(&self.config.name).await {
+            return;
+        }
+
+        let mut detected_files = DETECTED_FILES.lock().await;
+        for path in paths {
+            // Directories are skipped so the title folders created while sorting aren't sorted too
+            if !self.fs.is_file(&path)
+                || path.ends_with(".partial")
+                || detected_files.contains(&path)
+                || self.config.is_ignored(&path)
+            {
+                continue;
+            }
+
+            detected_files.insert(path.clone());
+            let (config, fs, transfers) = (
+                rules::current(&self.config),
+                self.fs.clone(),
+                self.transfers.clone(),
+            );
+            tokio::spawn(
+                async move {
+                    if let Some(mover) =
+                        Mover::new_in_background(config, fs, path, None, transfers).await
+                    {
+                        mover.start().await;
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+}
+
+impl From<PathConfig> for SortWatcher {
+    fn from(path: PathConfig) -> SortWatcher {
+        Self::new(path)
+    }
+}
@@ -0,0 +1,79 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+use tracing::error;
+
+// Everything that has to survive a restart, persisted to Config.state_file
+#[derive(Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(default)]
+    pub pending_deletions: Vec<PendingDeletion>,
+    // Unix timestamp in seconds of each path's last startup scan, by path name
+    #[serde(default)]
+    pub scan_watermarks: HashMap<String, u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PendingDeletion {
+    pub path: PathBuf,
+    // Unix timestamp in seconds
+    pub delete_at: u64,
+}
+
+#[derive(Default)]
+struct StateStore {
+    path: Option<PathBuf>,
+    state: State,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<StateStore> = Mutex::new(StateStore::default());
+}
+
+// Without a state file everything is kept in memory only
+pub async fn init(path: Option<PathBuf>) -> Result<(), anyhow::Error> {
+    let state = match &path {
+        Some(path) if path.exists() => toml::from_str(&tokio::fs::read_to_string(path).await?)?,
+        _ => State::default(),
+    };
+
+    *STATE.lock().await = StateStore { path, state };
+    Ok(())
+}
+
+pub async fn update(change: impl FnOnce(&mut State)) {
+    let mut store = STATE.lock().await;
+    change(&mut store.state);
+
+    let Some(path) = &store.path else {
+        return;
+    };
+
+    let contents = match toml::to_string(&store.state) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error!("Could not serialize state: {}", e);
+            return;
+        }
+    };
+
+    // Write then rename so a crash mid-write never leaves a truncated state file
+    let temp_path = path.with_extension("tmp");
+    let result = match tokio::fs::write(&temp_path, contents).await {
+        Ok(_) => tokio::fs::rename(&temp_path, path).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("Could not write state file {}: {}", path.display(), e);
+    }
+}
+
+pub async fn pending_deletions() -> Vec<PendingDeletion> {
+    STATE.lock().await.state.pending_deletions.clone()
+}
+
+pub async fn scan_watermark(name: &str) -> Option<u64> {
+    STATE.lock().await.state.scan_watermarks.get(name).copied()
+}
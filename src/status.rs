@@ -0,0 +1,298 @@
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Mutex, broadcast};
+use tracing::{error, info};
+
+const RECENT_COMPLETIONS: usize = 20;
+
+// Live per-path counters shared by the watchers and movers, read by the status views
+#[derive(Clone, Default)]
+pub struct Status {
+    pub paths: BTreeMap<String, PathStatus>,
+    // Newest first
+    pub recent: VecDeque<Completion>,
+}
+
+#[derive(Clone, Default)]
+pub struct PathStatus {
+    pub state: WatcherState,
+    pub current_file: Option<PathBuf>,
+    // How far copying current_file got. Only copies that log progress (progress_secs or
+    // progress_percent) count their bytes, the others leave the copy to the kernel
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    // Movers waiting for their file to finish downloading
+    pub queued: usize,
+    pub in_flight: usize,
+    // Filesystem events received from the watcher
+    pub events: u64,
+    pub moved: u64,
+    pub errors: u64,
+    // Failures since the last successful move, for pause_after_errors
+    pub consecutive_errors: u64,
+    // Stopped processing events after too many failures in a row, until resumed
+    pub paused: bool,
+    pub paused_since: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum WatcherState {
+    #[default]
+    Starting,
+    Syncing,
+    Watching,
+}
+
+#[derive(Clone)]
+pub struct Completion {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+// A paused path that was resumed, events it dropped meanwhile have to be caught up on
+#[derive(Clone)]
+pub(crate) struct Resumed {
+    name: String,
+    paused_since: SystemTime,
+}
+
+lazy_static! {
+    static ref STATUS: Mutex<Status> = Mutex::new(Status::default());
+    static ref RESUMED: broadcast::Sender<Resumed> = broadcast::channel(16).0;
+}
+
+pub async fn update(name: &str, change: impl FnOnce(&mut PathStatus)) {
+    let mut status = STATUS.lock().await;
+    change(status.paths.entry(name.to_string()).or_default());
+}
+
+// For a path whose watcher stopped for good, e.g. a folder that no longer matches its glob source
+pub(crate) async fn remove(name: &str) {
+    STATUS.lock().await.paths.remove(name);
+}
+
+pub async fn complete(name: &str, source: &Path, destination: PathBuf) {
+    let mut status = STATUS.lock().await;
+    let path_status = status.paths.entry(name.to_string()).or_default();
+    path_status.moved += 1;
+    path_status.consecutive_errors = 0;
+    if path_status.current_file.as_deref() == Some(source) {
+        path_status.current_file = None;
+        path_status.bytes_done = 0;
+        path_status.bytes_total = 0;
+    }
+
+    status.recent.push_front(Completion {
+        name: name.to_string(),
+        path: destination,
+    });
+    status.recent.truncate(RECENT_COMPLETIONS);
+}
+
+// Called by the blocking copy for every chunk, so when the status is locked this update is skipped
+// and the next chunk's goes through instead. Returns false when it was skipped
+pub(crate) fn record_progress(name: &str, file: &Path, done: u64, total: u64) -> bool {
+    let Ok(mut status) = STATUS.try_lock() else {
+        return false;
+    };
+    if let Some(path_status) = status.paths.get_mut(name)
+        && path_status.current_file.as_deref() == Some(file)
+    {
+        path_status.bytes_done = done;
+        path_status.bytes_total = total;
+    }
+    true
+}
+
+pub async fn record_event(name: &str) {
+    update(name, |status| status.events += 1).await;
+}
+
+// Counts a failure, pausing the path once `pause_after` of them happened in a row
+pub async fn record_error(name: &str, pause_after: Option<u64>) {
+    let mut status = STATUS.lock().await;
+    let path_status = status.paths.entry(name.to_string()).or_default();
+    path_status.errors += 1;
+    path_status.consecutive_errors += 1;
+
+    if !path_status.paused
+        && pause_after.is_some_and(|limit| path_status.consecutive_errors >= limit)
+    {
+        path_status.paused = true;
+        path_status.paused_since = Some(SystemTime::now());
+        error!(
+            "{name} paused due to repeated failures ({} in a row), resume it through the control socket",
+            path_status.consecutive_errors
+        );
+    }
+}
+
+pub async fn is_paused(name: &str) -> bool {
+    STATUS
+        .lock()
+        .await
+        .paths
+        .get(name)
+        .is_some_and(|path_status| path_status.paused)
+}
+
+// Returns false when the path isn't paused
+pub async fn resume(name: &str) -> bool {
+    let mut status = STATUS.lock().await;
+    let Some(path_status) = status
+        .paths
+        .get_mut(name)
+        .filter(|path_status| path_status.paused)
+    else {
+        return false;
+    };
+
+    path_status.paused = false;
+    path_status.consecutive_errors = 0;
+    let paused_since = path_status
+        .paused_since
+        .take()
+        .unwrap_or_else(SystemTime::now);
+    info!("{name} resumed");
+    // Nobody listening only means no watcher is running for it
+    let _ = RESUMED.send(Resumed {
+        name: name.to_string(),
+        paused_since,
+    });
+    true
+}
+
+pub(crate) fn subscribe_resumes() -> broadcast::Receiver<Resumed> {
+    RESUMED.subscribe()
+}
+
+// Completes with when the path was paused once `name` is resumed
+pub(crate) async fn resumed(resumes: &mut broadcast::Receiver<Resumed>, name: &str) -> SystemTime {
+    loop {
+        match resumes.recv().await {
+            Ok(resumed) if resumed.name == name => return resumed.paused_since,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+// Whether the path is past its startup pass and handling events
+pub async fn is_watching(name: &str) -> bool {
+    let status = STATUS.lock().await;
+    status
+        .paths
+        .get(name)
+        .is_some_and(|path| matches!(path.state, WatcherState::Watching))
+}
+
+// Every `period`, logs what each path did since the previous heartbeat, so long quiet stretches
+// still show the daemon is alive
+pub async fn heartbeat_periodically(period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.tick().await;
+    let mut previous = snapshot().await.paths;
+    loop {
+        interval.tick().await;
+        let current = snapshot().await.paths;
+        for (name, path_status) in &current {
+            let before = previous.get(name).cloned().unwrap_or_default();
+            info!(
+                "{name}: {}, {} events, {} moved, {} errors in the last {period:?}, {} in flight, {} queued",
+                path_status.state_label(),
+                path_status.events - before.events,
+                path_status.moved - before.moved,
+                path_status.errors - before.errors,
+                path_status.in_flight,
+                path_status.queued
+            );
+        }
+        previous = current;
+    }
+}
+
+pub async fn snapshot() -> Status {
+    STATUS.lock().await.clone()
+}
+
+// For readers running outside the async runtime
+pub fn snapshot_blocking() -> Status {
+    STATUS.blocking_lock().clone()
+}
+
+impl PathStatus {
+    pub(crate) fn start(&mut self, file: PathBuf) {
+        self.current_file = Some(file);
+        self.bytes_done = 0;
+        self.bytes_total = 0;
+    }
+
+    // None when nothing is copying or its bytes aren't counted
+    pub fn percent(&self) -> Option<u64> {
+        self.current_file.as_ref()?;
+        (self.bytes_done * 100).checked_div(self.bytes_total)
+    }
+
+    pub fn state_label(&self) -> String {
+        if self.paused {
+            "Paused".to_string()
+        } else {
+            self.state.to_string()
+        }
+    }
+}
+
+impl fmt::Display for WatcherState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            WatcherState::Starting => "Starting",
+            WatcherState::Syncing => "Syncing",
+            WatcherState::Watching => "Watching",
+        };
+        f.write_str(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resuming_a_paused_path_tells_its_watcher() {
+        let mut resumes = subscribe_resumes();
+        record_error("resumed-path", Some(1)).await;
+        assert!(is_paused("resumed-path").await);
+        let paused = SystemTime::now();
+
+        assert!(resume("resumed-path").await);
+        assert!(!is_paused("resumed-path").await);
+        assert!(resumed(&mut resumes, "resumed-path").await <= paused);
+        assert!(!resume("resumed-path").await);
+    }
+
+    #[tokio::test]
+    async fn progress_is_only_recorded_for_the_current_file() {
+        let (file, other) = (
+            Path::new("/source/Show - 01.mkv"),
+            Path::new("/source/Other"),
+        );
+        update("progress-path", |status| status.start(file.to_path_buf())).await;
+        assert_eq!(snapshot().await.paths["progress-path"].percent(), None);
+
+        // Other tests may hold the status for a moment
+        while !record_progress("progress-path", file, 25, 100) {}
+        while !record_progress("progress-path", other, 75, 100) {}
+        assert_eq!(snapshot().await.paths["progress-path"].percent(), Some(25));
+
+        complete(
+            "progress-path",
+            file,
+            PathBuf::from("/library/Show - 01.mkv"),
+        )
+        .await;
+        assert_eq!(snapshot().await.paths["progress-path"].percent(), None);
+    }
+}
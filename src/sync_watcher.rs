@@ -1,149 +1,891 @@
-use crate::{PathConfig, copy_file};
-use notify::{RecursiveMode, Watcher};
-use std::collections::HashSet;
+use crate::config::PathConfig;
+use crate::copy::CopyOptions;
+use crate::error::OrganizerError;
+use crate::filesystem::{FileSystem, RealFileSystem};
+use crate::status::{self, WatcherState};
+use crate::watch::Watched;
+use crate::{mount, permissions, watch};
+use notify::RecursiveMode;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use tracing::{error, info};
-use walkdir::{DirEntry, WalkDir};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tracing::{Instrument, error, info, warn};
 
-pub struct SyncWatcher(PathConfig);
+// Events for a path are held this long after the last one, so an atomic replace in the source
+// (remove then create) is a single copy over the old file instead of a delete racing the copy
+const REPLACE_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct SyncWatcher {
+    config: PathConfig,
+    fs: Arc<dyn FileSystem>,
+}
 
 impl SyncWatcher {
     pub fn new(config: PathConfig) -> Self {
-        Self(config)
+        Self {
+            config,
+            fs: Arc::new(RealFileSystem),
+        }
+    }
+
+    pub(crate) fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
     }
 
-    pub async fn start(&self) -> Result<(), anyhow::Error> {
-        info!("Starting {} thread. Beginning sync", self.0.name);
+    #[tracing::instrument(skip_all, fields(name = %self.config.name))]
+    pub async fn start(&self) -> Result<(), OrganizerError> {
+        info!("Starting {} thread. Beginning sync", self.config.name);
 
-        self.sync_dirs();
+        mount::wait_until_available(&self.config).await;
+        mount::wait_until_settled(&self.config).await;
+        let mut resumes = status::subscribe_resumes();
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Syncing
+        })
+        .await;
+        self.sync_dirs()
+            .await
+            .map_err(OrganizerError::copy(&self.config.source))?;
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Watching
+        })
+        .await;
 
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-        let mut watcher =
-            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
-                let event = event.unwrap();
-                match event.kind {
-                    notify::EventKind::Create(_) | notify::EventKind::Remove(_) => {
-                        tx.send(event).unwrap()
+        let events = self.config.watched_events();
+        let (mut watcher, mut rx) = watch::event_channel(
+            self.config.channel_capacity(),
+            self.config.poll_fallback(),
+            move |event| watch::classify(&event.kind).is_some_and(|kind| events.contains(&kind)),
+        )
+        .map_err(OrganizerError::watch(&self.config.source))?;
+
+        let path = self.config.source.clone();
+        watcher
+            .watch(Path::new(&path), RecursiveMode::Recursive)
+            .map_err(OrganizerError::watch(&path))?;
+        let mut resync = self.config.resync_interval().map(|period| {
+            let mut resync = tokio::time::interval_at(Instant::now() + period, period);
+            resync.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            resync
+        });
+        // Source paths with events still inside the replace window, by when it closes
+        let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+        loop {
+            let next_settled = pending.values().min().copied();
+            tokio::select! {
+                event = watch::next_event(&mut rx, &mut watcher, &path, RecursiveMode::Recursive) => {
+                    let event = match event {
+                        Some(Watched::Event(event)) => event,
+                        // A full sync also settles whatever is still pending
+                        Some(Watched::Dropped(_)) => {
+                            pending.clear();
+                            self.resync().await;
+                            continue;
+                        }
+                        None => break,
+                    };
+                    status::record_event(&self.config.name).await;
+                    if status::is_paused(&self.config.name).await {
+                        continue;
+                    }
+                    // An unmounting share reports its files as removed
+                    if !mount::source_available(&self.config) {
+                        warn!("Source is not mounted, ignoring {:?} event", event.kind);
+                        continue;
+                    }
+                    // A later event for the same path replaces the earlier one, what happened
+                    // is decided by what is there once the window closes
+                    let settles = Instant::now() + REPLACE_WINDOW;
+                    for path in event.paths {
+                        pending.insert(path, settles);
                     }
-                    _ => {}
                 }
-            })?;
-
-        let path = self.0.source.clone();
-        watcher.watch(Path::new(&path), RecursiveMode::Recursive)?;
-        while let Some(event) = rx.recv().await {
-            match event.kind {
-                notify::EventKind::Create(_) => self.copy_file(event.paths).await,
-                notify::EventKind::Remove(_) => self.delete_file(event.paths),
-                _ => unreachable!(),
+                _ = sleep_until_settled(next_settled) => self.apply_settled(&mut pending).await,
+                _ = next_resync(&mut resync) => self.resync().await,
+                // Events were dropped while paused, a full sync catches up on them
+                _ = status::resumed(&mut resumes, &self.config.name) => {
+                    pending.clear();
+                    self.resync().await;
+                }
             }
         }
 
         Ok(())
     }
 
+    // Whatever is still there is new or changed, whatever isn't was removed, the same as either
+    // side of a rename
+    async fn apply_settled(&self, pending: &mut HashMap<PathBuf, Instant>) {
+        let now = Instant::now();
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, settles)| **settles <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            pending.remove(path);
+        }
+        if !mount::source_available(&self.config) {
+            warn!(
+                "Source is not mounted, dropping {} pending events",
+                settled.len()
+            );
+            return;
+        }
+
+        let (present, gone): (Vec<_>, Vec<_>) =
+            settled.into_iter().partition(|path| self.fs.exists(path));
+        self.delete_file(gone).await;
+        self.copy_file(present).await;
+    }
+
+    async fn record_error(&self) {
+        status::record_error(&self.config.name, self.config.pause_after_errors).await;
+    }
+
+    // Catches changes the backend never reported, e.g. on network mounts
+    async fn resync(&self) {
+        if status::is_paused(&self.config.name).await {
+            return;
+        }
+        if !mount::source_available(&self.config) {
+            warn!("Source is not mounted, skipping the periodic resync");
+            return;
+        }
+
+        info!("Starting periodic resync");
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Syncing
+        })
+        .await;
+        if let Err(error) = self.sync_dirs().await {
+            error!(error = %error, "Periodic resync failed");
+            self.record_error().await;
+        }
+        status::update(&self.config.name, |status| {
+            status.state = WatcherState::Watching
+        })
+        .await;
+    }
+
     async fn copy_file(&self, paths: Vec<PathBuf>) {
         for path in paths {
-            let destination_name = self
-                .0
-                .destination
-                .join(path.strip_prefix(&self.0.source).unwrap());
-            let file_name = path.file_name().unwrap();
+            if self.config.is_ignored(&path) {
+                continue;
+            }
+
+            let Some(relative_path) = self.relative_path(&path) else {
+                error!(
+                    "{} is not inside {}",
+                    path.display(),
+                    self.config.source.display()
+                );
+                continue;
+            };
+            // The source root itself, e.g. when it is recreated
+            if relative_path.as_os_str().is_empty() {
+                continue;
+            }
+
+            let destination_name = self.config.destination.join(&relative_path);
+            let (Some(parent), Some(file_name)) = (destination_name.parent(), path.file_name())
+            else {
+                error!("Skipping {}, it has no destination folder", path.display());
+                continue;
+            };
+            if let Err(error) = self.fs.create_dir_all(parent) {
+                error!(error = %error, "Error creating {}", parent.display());
+                self.record_error().await;
+                continue;
+            }
+            permissions::apply_below(&self.config.destination, parent, self.config.dir_mode);
+
             info!("Copying {file_name:?} to {destination_name:?}");
-            std::fs::create_dir_all(destination_name.parent().unwrap()).unwrap();
-            match copy_file(path.clone(), destination_name).await {
-                Ok(_) => info!("Copied {file_name:?}"),
-                Err(error) => error!(error = %error, "Error while copying {file_name:?}"),
+            let current_file = path.clone();
+            status::update(&self.config.name, |status| {
+                status.in_flight += 1;
+                status.start(current_file);
+            })
+            .await;
+            match blocking_copy(
+                self.fs.clone(),
+                path.clone(),
+                destination_name.clone(),
+                self.config.copy_options(),
+            )
+            .await
+            {
+                Ok(_) => {
+                    info!("Copied {file_name:?}");
+                    permissions::apply(&destination_name, self.config.file_mode);
+                    status::complete(&self.config.name, &path, destination_name).await;
+                }
+                Err(error) => {
+                    error!(error = %error, "Error while copying {file_name:?}");
+                    self.record_error().await;
+                }
             }
+            status::update(&self.config.name, |status| status.in_flight -= 1).await;
         }
     }
 
-    fn delete_file(&self, paths: Vec<PathBuf>) {
+    async fn delete_file(&self, paths: Vec<PathBuf>) {
         for path in paths {
-            let destination_path = self
-                .0
-                .destination
-                .join(path.strip_prefix(&self.0.source).unwrap());
-            let file_name = path.file_name().unwrap();
-            info!("Deleting {file_name:?}");
-            match std::fs::remove_file(&destination_path) {
+            // Duplicate remove events and rename-as-delete saves can report files that are still there
+            if self.fs.exists(&path) {
+                info!(
+                    "Ignoring remove event for {}, it still exists in the source",
+                    path.display()
+                );
+                continue;
+            }
+
+            let Some(relative_path) = self.relative_path(&path) else {
+                error!(
+                    "{} is not inside {}",
+                    path.display(),
+                    self.config.source.display()
+                );
+                continue;
+            };
+            let destination_path = self.config.destination.join(relative_path);
+            let (Some(parent), Some(file_name)) = (destination_path.parent(), path.file_name())
+            else {
+                error!("Skipping remove event for {}", path.display());
+                continue;
+            };
+            if self.config.dry_run {
+                info!("Dry run: would delete {}", destination_path.display());
+                continue;
+            }
+
+            // A removed folder takes its mirrored copy along
+            let removed = if self.fs.is_dir(&destination_path) {
+                info!("Deleting folder {file_name:?}");
+                self.fs.remove_dir_all(&destination_path)
+            } else {
+                info!("Deleting {file_name:?}");
+                self.fs.remove_file(&destination_path)
+            };
+            match removed {
                 Ok(_) => {
-                    let parent = destination_path.parent().unwrap();
-                    let file_count = std::fs::read_dir(parent).unwrap().count();
-                    if file_count == 0 {
-                        std::fs::remove_dir(parent).unwrap();
-                    }
                     info!("Removed {file_name:?}");
+                    self.remove_empty_folder(parent);
+                }
+                // The events of a folder's files can come after the folder's was handled
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    info!("{file_name:?} is already gone from the destination");
+                    self.remove_empty_folder(parent);
+                }
+                Err(error) => {
+                    error!(error = %error, "Error deleting file {file_name:?}");
+                    self.record_error().await;
                 }
-                Err(error) => error!(error = %error, "Error deleting file {file_name:?}"),
             }
         }
     }
 
-    fn sync_dirs(&self) {
-        std::fs::create_dir_all(&self.0.source).unwrap();
-        std::fs::create_dir_all(&self.0.destination).unwrap();
+    // Folders emptied by a deletion go too, but never the destination root
+    fn remove_empty_folder(&self, folder: &Path) {
+        if folder == self.config.destination {
+            return;
+        }
 
-        let source_list = Self::scan_dir(&self.0.source);
-        let destination_list = Self::scan_dir(&self.0.destination);
+        let is_empty = self
+            .fs
+            .read_dir(folder)
+            .is_ok_and(|entries| entries.is_empty());
+        if is_empty && let Err(error) = self.fs.remove_dir(folder) {
+            error!(error = %error, "Error removing empty folder {}", folder.display());
+        }
+    }
 
-        SyncWatcher::remove_difference(&destination_list, &source_list);
-        SyncWatcher::remove_difference(&source_list, &destination_list);
+    // notify can report symlink-resolved paths that don't share the configured source's prefix,
+    // so fall back to comparing canonical forms
+    fn relative_path(&self, path: &Path) -> Option<PathBuf> {
+        if let Ok(relative_path) = path.strip_prefix(&self.config.source) {
+            return Some(relative_path.to_path_buf());
+        }
 
-        info!("Done syncing");
+        let source = self.config.source.canonicalize().ok()?;
+        canonicalize_existing(path)
+            .strip_prefix(&source)
+            .ok()
+            .map(Path::to_path_buf)
     }
 
-    fn remove_difference(base: &HashSet<FileCompare>, other: &HashSet<FileCompare>) {
-        base.difference(other).for_each(|fc| {
-            if fc.path.is_dir() {
-                std::fs::remove_dir_all(&fc.path).unwrap()
-            } else {
-                std::fs::remove_file(&fc.path).unwrap()
+    // One-way reconciliation: files missing from (or differing in size in) the destination are
+    // copied over, files the source no longer has are removed from the destination
+    pub(crate) async fn sync_dirs(&self) -> std::io::Result<()> {
+        // validate refuses these, an embedder's config may not have been through it
+        if let Some(problem) = self.config.sync_destination_problem() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "destination {} {problem}",
+                    self.config.destination.display()
+                ),
+            ));
+        }
+        self.fs.create_dir_all(&self.config.source)?;
+        self.fs.create_dir_all(&self.config.destination)?;
+
+        // An unreadable source would look empty and have the whole destination deleted
+        let source = self.scan_dir(&self.config.source)?;
+        let destination = self.scan_dir(&self.config.destination)?;
+        let (source_list, destination_list) = (&source.files, &destination.files);
+        let source_paths: HashSet<&PathBuf> = source_list.iter().map(|file| &file.path).collect();
+        let mut summary = SyncSummary::default();
+
+        for file in destination_list.iter().filter(|file| {
+            !source_paths.contains(&file.path)
+                && !source
+                    .unreadable
+                    .iter()
+                    .any(|folder| file.path.starts_with(folder))
+        }) {
+            let path = self.config.destination.join(&file.path);
+            if self.config.dry_run {
+                info!("Dry run: would remove {}", path.display());
+                continue;
             }
-        });
-    }
 
-    fn scan_dir(dir: &PathBuf) -> HashSet<FileCompare> {
-        let mut set = HashSet::new();
-        for entry in WalkDir::new(dir) {
-            let entry = match entry {
-                Ok(entry) => {
-                    if !entry.file_type().is_file() {
-                        continue;
+            match self.fs.remove_file(&path) {
+                Ok(_) => {
+                    info!("Removed {}, it is no longer in the source", path.display());
+                    summary.deleted += 1;
+                }
+                Err(error) => {
+                    error!(error = %error, "Error deleting {}", path.display());
+                    summary.failed += 1;
+                    self.record_error().await;
+                }
+            }
+        }
+
+        // Copies are independent of each other, so they run on a bounded pool
+        let workers = Arc::new(Semaphore::new(self.config.sync_workers()));
+        let mut copies = JoinSet::new();
+        for file in source_list.difference(destination_list) {
+            let source = self.config.source.join(&file.path);
+            let destination = self.config.destination.join(&file.path);
+            let root = self.config.destination.clone();
+            let (file_mode, dir_mode) = (self.config.file_mode, self.config.dir_mode);
+            let options = self.config.copy_options();
+            let fs = self.fs.clone();
+            let workers = workers.clone();
+            copies.spawn(
+                async move {
+                    let _permit = workers.acquire_owned().await;
+                    let parent = destination.parent().unwrap_or(&root);
+                    let copied = match fs.create_dir_all(parent) {
+                        Ok(_) => {
+                            permissions::apply_below(&root, parent, dir_mode);
+                            blocking_copy(fs, source, destination.clone(), options).await
+                        }
+                        Err(error) => Err(error),
+                    };
+                    if copied.is_ok() {
+                        permissions::apply(&destination, file_mode);
                     }
+                    (destination, copied)
+                }
+                .in_current_span(),
+            );
+        }
+
+        while let Some(copy) = copies.join_next().await {
+            match copy {
+                Ok((destination, Ok(bytes))) => {
+                    info!("Copied {}", destination.display());
+                    summary.copied += 1;
+                    summary.bytes += bytes;
+                }
+                Ok((destination, Err(error))) => {
+                    error!(error = %error, "Error copying {}", destination.display());
+                    summary.failed += 1;
+                    self.record_error().await;
+                }
+                Err(error) => {
+                    error!(error = %error, "Sync copy task failed");
+                    summary.failed += 1;
+                    self.record_error().await;
+                }
+            }
+        }
+
+        info!(
+            copied = summary.copied,
+            deleted = summary.deleted,
+            bytes = summary.bytes,
+            failed = summary.failed,
+            "Done syncing: copied {} files ({} bytes), deleted {} files, {} failed",
+            summary.copied,
+            summary.bytes,
+            summary.deleted,
+            summary.failed
+        );
 
-                    entry
+        Ok(())
+    }
+
+    // What a sync would change right now, without changing anything
+    pub(crate) fn drift(&self) -> std::io::Result<Drift> {
+        let source_list = self.scan_dir(&self.config.source)?.files;
+        let destination_list = self.scan_dir(&self.config.destination)?.files;
+        let sizes = |list: &HashSet<FileCompare>| -> HashMap<PathBuf, u64> {
+            list.iter()
+                .map(|file| (file.path.clone(), file.size))
+                .collect()
+        };
+        let (source_sizes, destination_sizes) = (sizes(&source_list), sizes(&destination_list));
+
+        let mut drift = Drift::default();
+        for (path, size) in &source_sizes {
+            match destination_sizes.get(path) {
+                None => drift.missing.push(path.clone()),
+                Some(destination_size) if destination_size != size => {
+                    drift.mismatched.push(path.clone())
                 }
+                Some(_) => {}
+            }
+        }
+        drift.extra = destination_sizes
+            .keys()
+            .filter(|path| !source_sizes.contains_key(*path))
+            .cloned()
+            .collect();
+
+        drift.missing.sort();
+        drift.extra.sort();
+        drift.mismatched.sort();
+        Ok(drift)
+    }
+
+    // Ignored entries are left out on both sides, so junk is neither copied nor removed. A
+    // symlinked root is followed, symlinks below it are not. Failing to read the root is an error,
+    // folders below it that can't be read are listed in `unreadable`
+    fn scan_dir(&self, dir: &Path) -> std::io::Result<Scan> {
+        let mut scan = Scan::default();
+        let mut folders = vec![dir.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            let entries = match self.fs.read_dir(&folder) {
+                Ok(entries) => entries,
+                Err(err) if folder == dir => return Err(err),
                 Err(err) => {
-                    error!("error for {err}");
+                    error!(error = %err, "Could not read {}", folder.display());
+                    if let Ok(relative) = folder.strip_prefix(dir) {
+                        scan.unreadable.push(relative.to_path_buf());
+                    }
                     continue;
                 }
             };
+            for entry in entries {
+                if entry
+                    .file_name()
+                    .is_some_and(|name| self.config.is_ignored_name(name))
+                    || self.fs.is_symlink(&entry)
+                {
+                    continue;
+                }
+
+                let metadata = match self.fs.metadata(&entry) {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        error!(error = %err, "Could not read {}", entry.display());
+                        // Whatever it is, the other side's copy is kept
+                        if let Ok(relative) = entry.strip_prefix(dir) {
+                            scan.unreadable.push(relative.to_path_buf());
+                        }
+                        continue;
+                    }
+                };
+                if metadata.is_dir {
+                    folders.push(entry);
+                    continue;
+                }
 
-            set.insert(entry.into());
+                // Compared by path relative to the scanned root so both sides line up
+                let Ok(path) = entry.strip_prefix(dir) else {
+                    continue;
+                };
+                scan.files.insert(FileCompare {
+                    path: path.to_path_buf(),
+                    size: metadata.len,
+                });
+            }
         }
 
-        set
+        Ok(scan)
+    }
+}
+
+// Never completes while no events are waiting out the replace window
+async fn sleep_until_settled(settles: Option<Instant>) {
+    match settles {
+        Some(settles) => tokio::time::sleep_until(settles).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Never completes when periodic resyncs are off
+async fn next_resync(resync: &mut Option<Interval>) {
+    match resync {
+        Some(resync) => {
+            resync.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+async fn blocking_copy(
+    fs: Arc<dyn FileSystem>,
+    source: PathBuf,
+    destination: PathBuf,
+    options: CopyOptions,
+) -> std::io::Result<u64> {
+    tokio::task::spawn_blocking(move || fs.copy(&source, &destination, None, options)).await?
+}
+
+// Canonicalizes the deepest ancestor that still exists, so paths of removed files resolve too
+fn canonicalize_existing(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |canonical, component| canonical.join(component));
+        }
+
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(file_name)) => {
+                missing.push(file_name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
     }
 }
 
 impl From<PathConfig> for SyncWatcher {
     fn from(path: PathConfig) -> SyncWatcher {
-        Self(path)
+        Self::new(path)
+    }
+}
+
+// Relative paths that differ between a Sync path's source and destination
+#[derive(Default)]
+pub(crate) struct Drift {
+    // In the source only
+    pub(crate) missing: Vec<PathBuf>,
+    // In the destination only
+    pub(crate) extra: Vec<PathBuf>,
+    // In both, with different sizes
+    pub(crate) mismatched: Vec<PathBuf>,
+}
+
+impl Drift {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
     }
 }
 
+#[derive(Default)]
+struct SyncSummary {
+    copied: u64,
+    deleted: u64,
+    bytes: u64,
+    failed: u64,
+}
+
+#[derive(Default)]
+struct Scan {
+    files: HashSet<FileCompare>,
+    // Relative to the scanned root
+    unreadable: Vec<PathBuf>,
+}
+
 #[derive(Debug, Eq, Hash, PartialEq)]
 struct FileCompare {
     path: PathBuf,
     size: u64,
 }
 
-impl From<DirEntry> for FileCompare {
-    fn from(entry: DirEntry) -> FileCompare {
-        Self {
-            path: entry.path().to_path_buf(),
-            size: entry.metadata().unwrap().len(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use crate::copy::Progress;
+    use crate::filesystem::{FileMetadata, MemoryFileSystem};
+
+    fn config(source: &Path, destination: &Path) -> PathConfig {
+        load(source, destination).unwrap().paths[0].clone()
+    }
+
+    fn load(source: &Path, destination: &Path) -> Result<Config, OrganizerError> {
+        Config::from_toml(
+            &format!(
+                r#"
+                [[paths]]
+                name = "mirror"
+                watcher_type = "Sync"
+                source = '{}'
+                destination = '{}'
+                place_in_sub = false
+                "#,
+                source.display(),
+                destination.display()
+            ),
+            Path::new("/config.toml"),
+        )
+    }
+
+    fn sync_watcher(fs: &Arc<MemoryFileSystem>) -> SyncWatcher {
+        SyncWatcher::new(config(Path::new("/source"), Path::new("/mirror")))
+            .with_file_system(fs.clone())
+    }
+
+    #[tokio::test]
+    async fn sync_copies_new_files_and_removes_extra_ones() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Gone - 01.mkv", "removed from the source");
+        let sync = sync_watcher(&fs);
+
+        sync.sync_dirs().await.unwrap();
+
+        assert_eq!(
+            fs.contents("/mirror/Show/Show - 01.mkv").as_deref(),
+            Some(&b"episode 1"[..])
+        );
+        assert!(!fs.exists(Path::new("/mirror/Gone - 01.mkv")));
+        assert!(fs.exists(Path::new("/source/Show/Show - 01.mkv")));
+    }
+
+    #[tokio::test]
+    async fn sync_replaces_files_that_differ_in_size() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show - 01.mkv", "the whole episode");
+        fs.add_file("/mirror/Show - 01.mkv", "partial");
+        let sync = sync_watcher(&fs);
+
+        sync.sync_dirs().await.unwrap();
+
+        assert_eq!(
+            fs.contents("/mirror/Show - 01.mkv").as_deref(),
+            Some(&b"the whole episode"[..])
+        );
+        assert!(sync.drift().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn remove_event_for_a_file_still_in_the_source_keeps_the_copy() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Show - 02.mkv", "episode 2");
+        let sync = sync_watcher(&fs);
+
+        // The second event is a duplicate of the first
+        let removed = vec![
+            PathBuf::from("/source/Show - 01.mkv"),
+            PathBuf::from("/source/Show - 01.mkv"),
+            PathBuf::from("/source/Show - 02.mkv"),
+        ];
+        sync.delete_file(removed).await;
+
+        assert!(fs.exists(Path::new("/mirror/Show - 01.mkv")));
+        assert!(!fs.exists(Path::new("/mirror/Show - 02.mkv")));
+    }
+
+    // notify reports the resolved paths of a symlinked source, they still mirror to the same
+    // relative layout
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinked_source_keeps_the_relative_layout() {
+        let root = std::env::temp_dir().join(format!(
+            "anime-organizer-sync-symlink-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let (real, link, mirror) = (root.join("real"), root.join("link"), root.join("mirror"));
+        std::fs::create_dir_all(real.join("Show")).unwrap();
+        std::fs::write(real.join("Show/Show - 01.mkv"), "episode 1").unwrap();
+        std::fs::write(real.join("Show - 02.mkv"), "episode 2").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        std::fs::create_dir_all(&mirror).unwrap();
+        let sync = SyncWatcher::new(config(&link, &mirror));
+
+        let resolved = real.canonicalize().unwrap().join("Show/Show - 01.mkv");
+        assert_eq!(
+            sync.relative_path(&resolved),
+            Some(PathBuf::from("Show/Show - 01.mkv"))
+        );
+        sync.copy_file(vec![resolved]).await;
+        assert_eq!(
+            std::fs::read(mirror.join("Show/Show - 01.mkv")).unwrap(),
+            b"episode 1"
+        );
+
+        sync.sync_dirs().await.unwrap();
+        assert_eq!(
+            std::fs::read(mirror.join("Show - 02.mkv")).unwrap(),
+            b"episode 2"
+        );
+        assert!(sync.drift().unwrap().is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn degenerate_destinations_are_refused() {
+        let degenerate = ["", "/", "/source", "/source/mirror"];
+        for destination in degenerate {
+            assert!(
+                load(Path::new("/source"), Path::new(destination)).is_err(),
+                "{destination:?} was accepted"
+            );
+        }
+
+        // A watcher built without validating neither writes nor deletes anything
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/source/Show - 01.mkv", "episode 1");
+        fs.add_file("/other/Other - 01.mkv", "not in the source");
+        for destination in degenerate {
+            let mut config = config(Path::new("/source"), Path::new("/mirror"));
+            config.destination = PathBuf::from(destination);
+            let sync = SyncWatcher::new(config).with_file_system(fs.clone());
+
+            assert!(
+                sync.sync_dirs().await.is_err(),
+                "{destination:?} was synced"
+            );
+            assert!(fs.exists(Path::new("/source/Show - 01.mkv")));
+            assert!(fs.exists(Path::new("/other/Other - 01.mkv")));
+            assert!(!fs.exists(Path::new("/source/mirror")));
+            assert!(!fs.exists(Path::new("/mirror")));
+        }
+    }
+
+    #[tokio::test]
+    async fn removed_folder_takes_its_copy_along_without_an_error() {
+        let fs = Arc::new(MemoryFileSystem::new());
+        fs.add_file("/mirror/Show/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Other - 01.mkv", "still in the source");
+        fs.add_file("/source/Other - 01.mkv", "still in the source");
+        let mut config = config(Path::new("/source"), Path::new("/mirror"));
+        config.name = "removed-folder".to_string();
+        config.pause_after_errors = Some(1);
+        let sync = SyncWatcher::new(config).with_file_system(fs.clone());
+
+        // The folder's file is reported after the folder itself
+        sync.delete_file(vec![
+            PathBuf::from("/source/Show"),
+            PathBuf::from("/source/Show/Show - 01.mkv"),
+        ])
+        .await;
+
+        assert!(!fs.exists(Path::new("/mirror/Show")));
+        assert!(fs.exists(Path::new("/mirror/Other - 01.mkv")));
+        assert!(!status::is_paused("removed-folder").await);
+    }
+
+    // A MemoryFileSystem on which `unreadable` can't be listed, like a share gone stale
+    struct UnreadableFolder {
+        fs: MemoryFileSystem,
+        unreadable: PathBuf,
+    }
+
+    impl FileSystem for UnreadableFolder {
+        fn copy(
+            &self,
+            source: &Path,
+            destination: &Path,
+            progress: Option<&Progress>,
+            options: CopyOptions,
+        ) -> std::io::Result<u64> {
+            self.fs.copy(source, destination, progress, options)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+            self.fs.rename(from, to)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.create_dir_all(path)
+        }
+
+        fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_file(path)
+        }
+
+        fn remove_dir(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_dir(path)
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+            self.fs.remove_dir_all(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+            self.fs.write(path, contents)
+        }
+
+        fn hard_link(&self, original: &Path, link: &Path) -> std::io::Result<()> {
+            self.fs.hard_link(original, link)
+        }
+
+        fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+            if path == self.unreadable {
+                return Err(std::io::Error::other("stale file handle"));
+            }
+            self.fs.read_dir(path)
         }
+
+        fn metadata(&self, path: &Path) -> std::io::Result<FileMetadata> {
+            self.fs.metadata(path)
+        }
+    }
+
+    fn unreadable(folder: &str) -> Arc<UnreadableFolder> {
+        let fs = MemoryFileSystem::new();
+        fs.add_file("/source/Show/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Show/Show - 01.mkv", "episode 1");
+        fs.add_file("/mirror/Other - 01.mkv", "episode 1");
+        Arc::new(UnreadableFolder {
+            fs,
+            unreadable: PathBuf::from(folder),
+        })
+    }
+
+    #[tokio::test]
+    async fn unreadable_source_leaves_the_destination_alone() {
+        let fs = unreadable("/source");
+        let sync = SyncWatcher::new(config(Path::new("/source"), Path::new("/mirror")))
+            .with_file_system(fs.clone());
+
+        assert!(sync.sync_dirs().await.is_err());
+        assert!(sync.drift().is_err());
+        assert!(fs.exists(Path::new("/mirror/Show/Show - 01.mkv")));
+        assert!(fs.exists(Path::new("/mirror/Other - 01.mkv")));
+    }
+
+    #[tokio::test]
+    async fn unreadable_source_folder_keeps_its_copies() {
+        let fs = unreadable("/source/Show");
+        let sync = SyncWatcher::new(config(Path::new("/source"), Path::new("/mirror")))
+            .with_file_system(fs.clone());
+
+        sync.sync_dirs().await.unwrap();
+
+        assert!(fs.exists(Path::new("/mirror/Show/Show - 01.mkv")));
+        assert!(!fs.exists(Path::new("/mirror/Other - 01.mkv")));
     }
 }
@@ -0,0 +1,198 @@
+use crate::metadata::Metadata;
+use std::path::{Path, PathBuf};
+
+// Renders a template such as `{title} - {episode:02}< [{resolution}]>`. Returns None when a
+// placeholder has no parsed value so callers can fall back to the original name, except inside an
+// optional `<>` section, which is dropped instead. `<` and `>` can't be in Windows file names, so
+// they never need to be literal
+pub fn render(template: &str, metadata: &Metadata) -> Option<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+
+        rendered.push_str(&render_placeholders(&rest[..start], metadata)?);
+        if let Some(section) = render_placeholders(&rest[start + 1..end], metadata) {
+            rendered.push_str(&section);
+        }
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(&render_placeholders(rest, metadata)?);
+    Some(rendered)
+}
+
+fn render_placeholders(template: &str, metadata: &Metadata) -> Option<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        rendered.push_str(&rest[..start]);
+        let (placeholder, spec) = match rest[start + 1..end].split_once(':') {
+            Some((placeholder, spec)) => (placeholder, Some(spec)),
+            None => (&rest[start + 1..end], None),
+        };
+        let value = metadata.value(placeholder.trim())?;
+        rendered.push_str(&apply_spec(value, spec));
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    Some(rendered)
+}
+
+// Renders a folder template one `/`-separated segment at a time, omitting segments whose
+// placeholders have no parsed value.
+pub fn render_path(template: &str, metadata: &Metadata) -> PathBuf {
+    template
+        .split('/')
+        .filter_map(|segment| render(segment, metadata))
+        .filter(|segment| !segment.trim().is_empty())
+        .collect()
+}
+
+// Which folder of render_path's result holds `placeholder`'s value, e.g. 1 for `{title}` in
+// `{title:first_letter}/{title}`. None when no rendered segment has it, or when the value spans
+// more than one folder because it contains a `/`
+pub fn folder_of(template: &str, metadata: &Metadata, placeholder: &str) -> Option<usize> {
+    let mut index = 0;
+    for segment in template.split('/') {
+        let Some(rendered) = render(segment, metadata).filter(|segment| !segment.trim().is_empty())
+        else {
+            continue;
+        };
+        let folders = Path::new(&rendered).components().count();
+        if has_placeholder(segment, placeholder) {
+            return (folders == 1).then_some(index);
+        }
+        index += folders;
+    }
+    None
+}
+
+// `first_letter` only buckets by the value, the folder with the value itself is elsewhere
+fn has_placeholder(segment: &str, placeholder: &str) -> bool {
+    segment
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}'))
+        .any(|(inside, _)| match inside.split_once(':') {
+            Some((name, spec)) => name.trim() == placeholder && spec != "first_letter",
+            None => inside.trim() == placeholder,
+        })
+}
+
+// `02` style specs zero-pad the leading number of a value, e.g. `1` -> `01`, `12.5` -> `12.5`,
+// and of both ends of a range, `1-12` -> `01-12`. `first_letter` keeps only the uppercased first
+// character, bucketing non-letters under `#`
+fn apply_spec(value: &str, spec: Option<&str>) -> String {
+    if spec == Some("first_letter") {
+        return first_letter(value);
+    }
+    if let Some((first, last)) = value.split_once('-') {
+        return format!("{}-{}", apply_spec(first, spec), apply_spec(last, spec));
+    }
+
+    let Some(width) = spec
+        .filter(|spec| spec.starts_with('0'))
+        .and_then(|spec| spec.parse::<usize>().ok())
+    else {
+        return value.to_string();
+    };
+
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    if digits == 0 {
+        return value.to_string();
+    }
+
+    format!("{:0>width$}{}", &value[..digits], &value[digits..])
+}
+
+fn first_letter(value: &str) -> String {
+    match value.trim_start().chars().next() {
+        Some(first) if first.is_alphabetic() => first.to_uppercase().collect(),
+        _ => "#".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode: Option<&str>) -> Metadata {
+        Metadata {
+            title: Some("Show".to_string()),
+            episode: episode.map(ToString::to_string),
+            ..Metadata::default()
+        }
+    }
+
+    #[test]
+    fn episode_is_zero_padded() {
+        assert_eq!(
+            render("{title} - {episode:02}", &episode(Some("1"))).as_deref(),
+            Some("Show - 01")
+        );
+        assert_eq!(
+            render("{title} - {episode:02}", &episode(Some("12"))).as_deref(),
+            Some("Show - 12")
+        );
+        assert_eq!(
+            render("{title} - {episode:02}", &episode(Some("123"))).as_deref(),
+            Some("Show - 123")
+        );
+    }
+
+    #[test]
+    fn missing_episode_fails_the_template_or_drops_its_section() {
+        assert_eq!(render("{title} - {episode:02}", &episode(None)), None);
+        assert_eq!(
+            render("{title}< - {episode:02}>", &episode(None)).as_deref(),
+            Some("Show")
+        );
+        assert_eq!(
+            render_path("{title}/Episode {episode:02}", &episode(None)),
+            PathBuf::from("Show")
+        );
+    }
+
+    #[test]
+    fn folder_of_skips_left_out_segments_and_buckets() {
+        let show = episode(None);
+        assert_eq!(folder_of("{title} ({year})", &show, "title"), None);
+        assert_eq!(
+            folder_of(
+                "{year}/{title:first_letter}/{title} - Anime",
+                &show,
+                "title"
+            ),
+            Some(1)
+        );
+
+        let slashed = Metadata {
+            title: Some("Fate/Zero".to_string()),
+            ..Metadata::default()
+        };
+        assert_eq!(folder_of("{title}", &slashed, "title"), None);
+    }
+
+    #[test]
+    fn both_ends_of_a_range_are_padded() {
+        assert_eq!(apply_spec("1-12", Some("02")), "01-12");
+        assert_eq!(apply_spec("01-12", Some("03")), "001-012");
+        assert_eq!(apply_spec("01-12", None), "01-12");
+        assert_eq!(
+            render("{title} - {episode:02}", &episode(Some("1-3"))).as_deref(),
+            Some("Show - 01-03")
+        );
+    }
+}
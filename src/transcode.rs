@@ -0,0 +1,77 @@
+use crate::config::TranscodeConfig;
+use anyhow::bail;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+use tracing::{error, info};
+
+const DEFAULT_PROGRAM: &str = "ffmpeg";
+
+// Transcodes the organized file next to itself and renames the result over it, so the library
+// only ever sees the original or the finished transcode. Files with other extensions are left be
+pub(crate) async fn transcode(config: &TranscodeConfig, file: &Path, dry_run: bool) {
+    let Some(extension) = file
+        .extension()
+        .map(|extension| extension.to_string_lossy())
+    else {
+        return;
+    };
+    if !config
+        .extensions
+        .iter()
+        .any(|wanted| wanted.eq_ignore_ascii_case(&extension))
+    {
+        return;
+    }
+
+    if dry_run {
+        info!("Dry run: would transcode {}", file.display());
+        return;
+    }
+
+    // Same extension, so the transcoder picks the same container
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let output = file.with_file_name(format!(".{file_name}.transcode.{extension}"));
+    info!("Transcoding {}", file.display());
+    match run(config, file, &output).await {
+        Ok(()) => match tokio::fs::rename(&output, file).await {
+            Ok(()) => info!("Transcoded {}", file.display()),
+            Err(e) => {
+                error!(
+                    "Could not replace {} with its transcode: {}",
+                    file.display(),
+                    e
+                );
+                let _ = tokio::fs::remove_file(&output).await;
+            }
+        },
+        Err(e) => {
+            error!(
+                "Transcoding {} failed, keeping the original: {}",
+                file.display(),
+                e
+            );
+            let _ = tokio::fs::remove_file(&output).await;
+        }
+    }
+}
+
+async fn run(config: &TranscodeConfig, input: &Path, output: &Path) -> Result<(), anyhow::Error> {
+    let status = Command::new(config.program.as_deref().unwrap_or(DEFAULT_PROGRAM))
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(input)
+        .args(&config.args)
+        .arg(output)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await?;
+    if !status.success() {
+        bail!("exited with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::status::{self, Status};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, List, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::Duration;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+// Runs until the user quits with `q` or Esc
+pub async fn run() -> Result<(), anyhow::Error> {
+    tokio::task::spawn_blocking(|| {
+        let mut terminal = ratatui::init();
+        let result = draw_loop(&mut terminal);
+        ratatui::restore();
+        result
+    })
+    .await?
+}
+
+fn draw_loop(terminal: &mut DefaultTerminal) -> Result<(), anyhow::Error> {
+    loop {
+        let status = status::snapshot_blocking();
+        terminal.draw(|frame| draw(frame, &status))?;
+
+        if event::poll(REFRESH_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+        {
+            return Ok(());
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, status: &Status) {
+    let [paths_area, recent_area] =
+        Layout::vertical([Constraint::Fill(1), Constraint::Length(10)]).areas(frame.area());
+
+    let header = Row::new([
+        "Path",
+        "State",
+        "Current file",
+        "Progress",
+        "Queued",
+        "In flight",
+        "Moved",
+        "Errors",
+    ]);
+    let rows = status.paths.iter().map(|(name, path_status)| {
+        let current_file = path_status
+            .current_file
+            .as_ref()
+            .and_then(|file| file.file_name())
+            .map(|file_name| file_name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Row::new([
+            name.clone(),
+            path_status.state_label(),
+            current_file,
+            path_status
+                .percent()
+                .map(|percent| format!("{percent}%"))
+                .unwrap_or_default(),
+            path_status.queued.to_string(),
+            path_status.in_flight.to_string(),
+            path_status.moved.to_string(),
+            path_status.errors.to_string(),
+        ])
+    });
+    let widths = [
+        Constraint::Length(16),
+        Constraint::Length(10),
+        Constraint::Fill(1),
+        Constraint::Length(8),
+        Constraint::Length(8),
+        Constraint::Length(9),
+        Constraint::Length(8),
+        Constraint::Length(8),
+    ];
+    let paths = Table::new(rows, widths)
+        .header(header)
+        .block(Block::bordered().title(" anime-organizer (q to quit) "));
+    frame.render_widget(paths, paths_area);
+
+    let recent = List::new(
+        status
+            .recent
+            .iter()
+            .map(|completion| format!("[{}] {}", completion.name, completion.path.display())),
+    )
+    .block(Block::bordered().title(" Recently moved "));
+    frame.render_widget(recent, recent_area);
+}
@@ -0,0 +1,59 @@
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+lazy_static! {
+    // Files seen since the last report
+    static ref UNPARSED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+// A file without a (confident) title, so it went to the destination root instead of its folder
+pub async fn record(file: PathBuf) {
+    UNPARSED.lock().await.push(file);
+}
+
+// Every REPORT_INTERVAL, lists the files recorded since the previous report and appends them to
+// `log` (Config.unparsed_log), giving a to-do list of names to fix or title rules to add
+pub async fn report_periodically(log: Option<PathBuf>) {
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let files = std::mem::take(&mut *UNPARSED.lock().await);
+        if files.is_empty() {
+            continue;
+        }
+
+        let names: Vec<String> = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        warn!(
+            "{} files had no title or lacked required_elements and were left in the destination root: {}",
+            files.len(),
+            names.join(", ")
+        );
+
+        if let Some(log) = &log
+            && let Err(e) = append(log, &names).await
+        {
+            error!("Could not write {}: {}", log.display(), e);
+        }
+    }
+}
+
+async fn append(log: &PathBuf, names: &[String]) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .await?;
+    let mut contents = names.join("\n");
+    contents.push('\n');
+    file.write_all(contents.as_bytes()).await
+}
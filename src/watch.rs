@@ -0,0 +1,228 @@
+use crate::config::WatchedEvent;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{
+    ErrorKind, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{Span, error, info, trace, warn};
+
+// Only exists on Linux, the only platform whose backend runs out of watches
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+// How long notify's thread waits for room in a full channel before the event is dropped
+const FULL_CHANNEL_WAIT: Duration = Duration::from_millis(500);
+const FULL_CHANNEL_RETRY: Duration = Duration::from_millis(10);
+
+type Handler = Arc<dyn Fn(notify::Result<Event>) + Send + Sync>;
+
+// When the first event was dropped since the watcher last caught up
+type Dropped = Arc<Mutex<Option<SystemTime>>>;
+
+// The native backend, or a PollWatcher once the native one ran out of watches
+pub struct EventWatcher {
+    inner: Box<dyn Watcher + Send>,
+    handler: Handler,
+    poll_fallback: Option<Duration>,
+    dropped: Dropped,
+}
+
+pub enum Watched {
+    Event(Event),
+    // Events were dropped because the channel stayed full, whatever changed since then has to be
+    // found by rescanning
+    Dropped(SystemTime),
+}
+
+// Creates a watcher whose wanted events, and any backend errors, are forwarded into a bounded
+// channel.
+//
+// When the channel is full, notify's thread waits up to FULL_CHANNEL_WAIT for room and then drops
+// the event. It can't wait for good: re-establishing a lost watch unwatches and watches from the
+// task draining the channel, and both wait for that same thread. A dropped event is reported by
+// next_event as Watched::Dropped so the watcher rescans instead.
+pub fn event_channel(
+    capacity: usize,
+    poll_fallback: Option<Duration>,
+    wanted: impl Fn(&Event) -> bool + Send + Sync + 'static,
+) -> notify::Result<(EventWatcher, Receiver<notify::Result<Event>>)> {
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    // The handler runs on notify's thread, so it is attached to the creating watcher's span by hand
+    let span = Span::current();
+    let dropped = Dropped::default();
+    let dropped_by = dropped.clone();
+    let handler: Handler = Arc::new(move |event: notify::Result<Event>| {
+        if let Ok(event) = &event
+            && !wanted(event)
+        {
+            // Shows what was received but ignored when debugging a change that didn't come through
+            trace!(parent: &span, kind = ?event.kind, paths = ?event.paths, "Ignoring event");
+            return;
+        }
+        forward(&tx, &dropped_by, event);
+    });
+
+    let forward_to = handler.clone();
+    let inner = notify::recommended_watcher(move |event| forward_to(event))?;
+    let watcher = EventWatcher {
+        inner: Box::new(inner),
+        handler,
+        poll_fallback,
+        dropped,
+    };
+    Ok((watcher, rx))
+}
+
+impl EventWatcher {
+    // Running out of inotify watches on a large tree fails with a message that says nothing about
+    // the limit, so it is explained here, and with poll_fallback_secs a PollWatcher takes over
+    pub fn watch(&mut self, root: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        let error = match self.inner.watch(root, mode) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if !matches!(error.kind, ErrorKind::MaxFilesWatch) {
+            return Err(error);
+        }
+
+        let limit = std::fs::read_to_string(MAX_USER_WATCHES)
+            .map(|limit| format!(" (currently {})", limit.trim()))
+            .unwrap_or_default();
+        error!(
+            "Ran out of inotify watches watching {}. Raise fs.inotify.max_user_watches{limit}, e.g. \
+             `sysctl fs.inotify.max_user_watches=524288`, or set poll_fallback_secs",
+            root.display()
+        );
+
+        let Some(interval) = self.poll_fallback else {
+            return Err(error);
+        };
+        warn!("Polling {} every {interval:?} instead", root.display());
+        let _ = self.inner.unwatch(root);
+        let forward_to = self.handler.clone();
+        let config = notify::Config::default().with_poll_interval(interval);
+        let mut poll = PollWatcher::new(move |event| forward_to(event), config)?;
+        poll.watch(root, mode)?;
+        self.inner = Box::new(poll);
+        Ok(())
+    }
+
+    fn unwatch(&mut self, root: &Path) -> notify::Result<()> {
+        self.inner.unwatch(root)
+    }
+
+    fn take_dropped(&self) -> Option<SystemTime> {
+        self.dropped
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take()
+    }
+}
+
+// The configurable group an event belongs to. Renames reported as a single event with both paths
+// are left out, the backends that send those also report each side on its own
+pub fn classify(kind: &EventKind) -> Option<WatchedEvent> {
+    match kind {
+        EventKind::Create(_) => Some(WatchedEvent::Create),
+        EventKind::Remove(_) => Some(WatchedEvent::Remove),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => None,
+        EventKind::Modify(ModifyKind::Name(_)) => Some(WatchedEvent::Rename),
+        EventKind::Modify(_) => Some(WatchedEvent::Modify),
+        _ => None,
+    }
+}
+
+// Waits for the next event, or for word that some were dropped. Backend errors in between are
+// logged, and when they mean the watch on `root` was lost (the folder was removed and recreated, a
+// mount went away) it is re-established
+pub async fn next_event(
+    rx: &mut Receiver<notify::Result<Event>>,
+    watcher: &mut EventWatcher,
+    root: &Path,
+    mode: RecursiveMode,
+) -> Option<Watched> {
+    loop {
+        if let Some(since) = watcher.take_dropped() {
+            return Some(Watched::Dropped(since));
+        }
+        let error = match rx.recv().await? {
+            Ok(event) => return Some(Watched::Event(event)),
+            Err(error) => error,
+        };
+
+        error!(
+            backend = ?RecommendedWatcher::kind(),
+            paths = ?error.paths,
+            "Watcher error on {}: {}",
+            root.display(),
+            error
+        );
+
+        if matches!(
+            error.kind,
+            ErrorKind::PathNotFound | ErrorKind::WatchNotFound
+        ) {
+            rewatch(watcher, root, mode);
+        }
+    }
+}
+
+fn rewatch(watcher: &mut EventWatcher, root: &Path, mode: RecursiveMode) {
+    // Removing a watch that is already gone fails, which is fine
+    let _ = watcher.unwatch(root);
+    match watcher.watch(root, mode) {
+        Ok(()) => info!("Watching {} again", root.display()),
+        Err(e) => error!("Could not watch {} again: {}", root.display(), e),
+    }
+}
+
+fn forward(tx: &Sender<notify::Result<Event>>, dropped: &Dropped, event: notify::Result<Event>) {
+    let deadline = Instant::now() + FULL_CHANNEL_WAIT;
+    let mut event = event;
+    loop {
+        match tx.try_send(event) {
+            Ok(()) => return,
+            Err(TrySendError::Full(full)) if Instant::now() < deadline => {
+                event = full;
+                std::thread::sleep(FULL_CHANNEL_RETRY);
+            }
+            Err(TrySendError::Full(_)) => break,
+            // The receiving watcher has stopped, so the event has nowhere to go
+            Err(TrySendError::Closed(_)) => return,
+        }
+    }
+
+    let mut dropped = dropped
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if dropped.is_none() {
+        warn!(
+            "Event channel stayed full for {FULL_CHANNEL_WAIT:?}, dropping events until the \
+             watcher catches up with a rescan"
+        );
+        *dropped = Some(SystemTime::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_channel_drops_the_event_and_asks_for_a_rescan() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let dropped = Dropped::default();
+
+        forward(&tx, &dropped, Ok(Event::new(EventKind::Any)));
+        assert!(dropped.lock().unwrap().is_none());
+        // Nothing drains the channel, so this one gives up after FULL_CHANNEL_WAIT
+        forward(&tx, &dropped, Ok(Event::new(EventKind::Any)));
+        assert!(dropped.lock().unwrap().is_some());
+
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+    }
+}